@@ -0,0 +1,417 @@
+//! compact.rs contains:
+//! 1. trait Compact: a small, versioned binary encoding.
+//! 2. varint helpers shared by all Compact impls.
+//! 3. Compact for FlowArena: id table + length-prefixed nodes + varint child indices
+//!    + forest + dormant chunks.
+
+use super::{Node, FlowArena, Root};
+use std::{collections::{HashMap, HashSet}, fmt, hash::Hash, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+/// magic header of an encoded FlowArena.
+pub const ARENA_MAGIC: &[u8; 4] = b"FLAR";
+/// current arena layout version; bumped whenever the layout changes.
+pub const ARENA_VERSION: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactError {
+    UnexpectedEnd,
+    VarintOverflow,
+    BadMagic,
+    UnknownVersion(u8),
+    InvalidUtf8,
+    InvalidTag(u8),
+    InvalidIndex(u64),
+    DuplicateId,
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CompactError::*;
+        match self {
+            UnexpectedEnd => write!(f, "unexpected end of input"),
+            VarintOverflow => write!(f, "varint does not fit in u64"),
+            BadMagic => write!(f, "bad magic header"),
+            UnknownVersion(v) => write!(f, "unknown format version {}", v),
+            InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            InvalidTag(t) => write!(f, "invalid tag byte {}", t),
+            InvalidIndex(i) => write!(f, "node index {} out of the id table", i),
+            DuplicateId => write!(f, "duplicate id in the id table"),
+            TrailingBytes(n) => write!(f, "{} trailing bytes after the payload", n),
+        }
+    }
+}
+
+impl std::error::Error for CompactError {}
+
+/// binary encoding; `decode` consumes exactly what `encode` produced from the front of `src`.
+pub trait Compact: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError>;
+
+    fn to_compact(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+    /// decodes the whole slice; err if anything is left over.
+    fn from_compact(bytes: &[u8]) -> Result<Self, CompactError> {
+        let mut src = bytes;
+        let res = Self::decode(&mut src)?;
+        if src.is_empty() { Ok(res) } else { Err(CompactError::TrailingBytes(src.len())) }
+    }
+}
+
+/// LEB128, 7 bits per byte.
+pub fn encode_varint(mut v: u64, buf: &mut Vec<u8>) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub fn decode_varint(src: &mut &[u8]) -> Result<u64, CompactError> {
+    let mut res: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *decode_bytes(src, 1)?.first().expect("one byte");
+        let low = (byte & 0x7f) as u64;
+        if shift == 63 && low > 1 {
+            return Err(CompactError::VarintOverflow);
+        }
+        res |= low << shift;
+        if byte & 0x80 == 0 {
+            return Ok(res);
+        }
+    }
+    Err(CompactError::VarintOverflow)
+}
+
+/// takes n raw bytes from the front of src.
+pub fn decode_bytes<'a>(src: &mut &'a [u8], n: usize) -> Result<&'a [u8], CompactError> {
+    if src.len() < n {
+        return Err(CompactError::UnexpectedEnd);
+    }
+    let (head, tail) = src.split_at(n);
+    *src = tail;
+    Ok(head)
+}
+
+/// a varint length followed by that many bytes.
+pub fn encode_len_prefixed(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+pub fn decode_len_prefixed<'a>(src: &mut &'a [u8]) -> Result<&'a [u8], CompactError> {
+    let len = decode_varint(src)?;
+    decode_bytes(src, len as usize)
+}
+
+impl Compact for () {
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+    fn decode(_src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(())
+    }
+}
+
+impl Compact for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        match u8::decode(src)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            t => Err(CompactError::InvalidTag(t)),
+        }
+    }
+}
+
+impl Compact for u8 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(decode_bytes(src, 1)?[0])
+    }
+}
+
+impl Compact for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(*self as u64, buf)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let v = decode_varint(src)?;
+        if v > u32::MAX as u64 { Err(CompactError::VarintOverflow) } else { Ok(v as u32) }
+    }
+}
+
+impl Compact for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(*self, buf)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        decode_varint(src)
+    }
+}
+
+impl Compact for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_len_prefixed(self.as_bytes(), buf)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let bytes = decode_len_prefixed(src)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CompactError::InvalidUtf8)
+    }
+}
+
+impl<T: Compact> Compact for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            None => buf.push(0),
+            Some(x) => {
+                buf.push(1);
+                x.encode(buf)
+            }
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        match u8::decode(src)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(src)?)),
+            t => Err(CompactError::InvalidTag(t)),
+        }
+    }
+}
+
+impl<T: Compact> Compact for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.len() as u64, buf);
+        self.iter().for_each(|x| x.encode(buf));
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let len = decode_varint(src)?;
+        // every element takes at least one byte, except zero-sized ones; don't trust len blindly.
+        let mut vec = Vec::with_capacity((len as usize).min(src.len()));
+        for _ in 0..len {
+            vec.push(T::decode(src)?);
+        }
+        Ok(vec)
+    }
+}
+
+/// secs and nanos since UNIX_EPOCH; earlier times are clamped to UNIX_EPOCH.
+impl Compact for SystemTime {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let dur = self.duration_since(UNIX_EPOCH).unwrap_or_default();
+        encode_varint(dur.as_secs(), buf);
+        encode_varint(dur.subsec_nanos() as u64, buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let secs = decode_varint(src)?;
+        let nanos = u32::decode(src)?;
+        if nanos >= 1_000_000_000 {
+            return Err(CompactError::VarintOverflow);
+        }
+        UNIX_EPOCH.checked_add(Duration::new(secs, nanos)).ok_or(CompactError::VarintOverflow)
+    }
+}
+
+impl<Id: Compact + Clone + Hash + Eq, Entity: Compact> FlowArena<Id, Entity> {
+    /// root first, then depth first from root and each tree in forest, then whatever is unreachable.
+    fn compact_order(&self) -> Vec<&Id> {
+        let mut order: Vec<&Id> = Vec::with_capacity(self.node_map.len());
+        let mut visited: HashSet<&Id> = HashSet::with_capacity(self.node_map.len());
+        let mut stack: Vec<&Id> = self.forest.iter().rev().map(|tree| &tree.id).collect();
+        stack.push(&self.root);
+        while let Some(id) = stack.pop() {
+            if visited.contains(id) { continue }
+            if let Some(node) = self.node_map.get(id) {
+                visited.insert(id);
+                order.push(node.id());
+                stack.extend(node.children.iter().rev());
+            }
+        }
+        for id in self.node_map.keys() {
+            if visited.insert(id) {
+                order.push(id);
+            }
+        }
+        order
+    }
+}
+
+/// encodes nodes in order, each length-prefixed: 
+/// `Entity | parent: varint (0 for none / outside, else index + 1) | k: varint | k * child index: varint`.
+pub(crate) fn encode_nodes<Id: Hash + Eq, Entity: Compact>(
+    order: &[&Id], index: &HashMap<&Id, usize>, node_map: &HashMap<Id, Node<Id, Entity>>, buf: &mut Vec<u8>
+) {
+    let mut node_buf = Vec::new();
+    for id in order.iter() {
+        let node = &node_map[*id];
+        node_buf.clear();
+        node.entity.encode(&mut node_buf);
+        let parent = node.parent.as_ref().and_then(|p| index.get(p)).map_or(0, |i| i + 1);
+        encode_varint(parent as u64, &mut node_buf);
+        let children: Vec<usize> = node.children.iter().filter_map(|c| index.get(c).cloned()).collect();
+        encode_varint(children.len() as u64, &mut node_buf);
+        children.into_iter().for_each(|c| encode_varint(c as u64, &mut node_buf));
+        encode_len_prefixed(&node_buf, buf);
+    }
+}
+
+/// decodes what encode_nodes wrote for the first len ids; parent 0 decodes to outside.
+pub(crate) fn decode_nodes<Id: Clone, Entity: Compact>(
+    src: &mut &[u8], ids: &[Id], len: usize, outside: Option<Id>
+) -> Result<Vec<Node<Id, Entity>>, CompactError> {
+    let lookup = |i: u64| ids.get(i as usize).cloned().ok_or(CompactError::InvalidIndex(i));
+    let mut nodes = Vec::with_capacity(len.min(src.len()));
+    for id in ids.iter().take(len) {
+        let mut node_src = decode_len_prefixed(src)?;
+        let mut node = Node::from_id(id.clone(), Entity::decode(&mut node_src)?);
+        node.parent = match decode_varint(&mut node_src)? {
+            0 => outside.clone(),
+            i => Some(lookup(i - 1)?),
+        };
+        let k = decode_varint(&mut node_src)?;
+        for _ in 0..k {
+            node.children.push(lookup(decode_varint(&mut node_src)?)?);
+        }
+        if !node_src.is_empty() {
+            return Err(CompactError::TrailingBytes(node_src.len()));
+        }
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// layout:
+/// ```text
+/// "FLAR" | version: u8
+/// n: varint | n * Id                                 (id table; index 0 is root)
+/// r: varint                                          (first r ids are resident; since version 3)
+/// r * ( len: varint | Entity | parent: varint        (0 for none, else index + 1)
+///     | k: varint | k * child index: varint )
+/// m: varint | m * ( name: String | index: varint )   (forest; since version 2)
+/// c: varint | c * ( stub index: varint | chunk: len-prefixed )   (dormant; since version 3)
+/// ```
+/// ids after the first r are the dormant children of stubs.
+impl<Id: Compact + Clone + Hash + Eq, Entity: Compact> Compact for FlowArena<Id, Entity> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(ARENA_MAGIC);
+        buf.push(ARENA_VERSION);
+        let mut order = self.compact_order();
+        let resident = order.len();
+        let stubs: Vec<&Id> = order.iter().cloned().filter(|id| self.dormant.contains_key(*id)).collect();
+        for stub in stubs.iter() {
+            let node = &self.node_map[*stub];
+            order.extend(node.children.iter().filter(|c| !self.node_map.contains_key(*c)));
+        }
+        let index: HashMap<&Id, usize> = order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        encode_varint(order.len() as u64, buf);
+        order.iter().for_each(|id| id.encode(buf));
+        encode_varint(resident as u64, buf);
+        encode_nodes(&order[..resident], &index, &self.node_map, buf);
+        encode_varint(self.forest.len() as u64, buf);
+        for tree in self.forest.iter() {
+            tree.name.encode(buf);
+            encode_varint(index[&tree.id] as u64, buf);
+        }
+        encode_varint(stubs.len() as u64, buf);
+        for stub in stubs.iter() {
+            encode_varint(index[*stub] as u64, buf);
+            encode_len_prefixed(&self.dormant[*stub], buf);
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        if decode_bytes(src, ARENA_MAGIC.len())? != ARENA_MAGIC {
+            return Err(CompactError::BadMagic);
+        }
+        let version = match u8::decode(src)? {
+            v @ 1..=ARENA_VERSION => v,
+            v => return Err(CompactError::UnknownVersion(v)),
+        };
+        let ids: Vec<Id> = Vec::decode(src)?;
+        let lookup = |i: u64| ids.get(i as usize).cloned().ok_or(CompactError::InvalidIndex(i));
+        let root = lookup(0)?;
+        let resident = if version >= 3 { decode_varint(src)? as usize } else { ids.len() };
+        if resident > ids.len() {
+            return Err(CompactError::InvalidIndex(resident as u64));
+        }
+        let mut node_map = HashMap::with_capacity(resident);
+        for node in decode_nodes::<Id, Entity>(src, &ids, resident, None)? {
+            if node_map.insert(node.id().clone(), node).is_some() {
+                return Err(CompactError::DuplicateId);
+            }
+        }
+        let mut forest = Vec::new();
+        if version >= 2 {
+            for _ in 0..decode_varint(src)? {
+                let name = String::decode(src)?;
+                forest.push(Root { name, id: lookup(decode_varint(src)?)? });
+            }
+        }
+        let mut dormant = HashMap::new();
+        if version >= 3 {
+            for _ in 0..decode_varint(src)? {
+                let stub = lookup(decode_varint(src)?)?;
+                dormant.insert(stub, decode_len_prefixed(src)?.to_vec());
+            }
+        }
+        Ok(FlowArena { root, forest, node_map, dormant })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Flow;
+    type FlowEntity = FlowArena<u64, String>;
+
+    fn make_flow() -> FlowEntity {
+        let mut flow: FlowEntity = FlowArena::new();
+        for i in 1..8 {
+            flow.grow(Node::from_id(i, format!(":{}:", i))).ok();
+        }
+        flow.devote_push(&2, &1).ok();
+        flow.devote_push(&3, &1).ok();
+        flow.devote_push(&4, &3).ok();
+        flow.devote(&5, &3, 0).ok();
+        flow.plant("other", Node::from_id(9, String::from(":9:"))).ok();
+        flow.devote_push(&6, &9).ok();
+        flow
+    }
+
+    #[test]
+    fn varint() {
+        for v in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX].iter() {
+            let mut buf = Vec::new();
+            encode_varint(*v, &mut buf);
+            let mut src = buf.as_slice();
+            assert_eq!(decode_varint(&mut src), Ok(*v));
+            assert!(src.is_empty());
+        }
+        assert_eq!(decode_varint(&mut &[0xff; 11][..]), Err(CompactError::VarintOverflow));
+        assert_eq!(decode_varint(&mut &[0x80][..]), Err(CompactError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn round_trip() {
+        let flow = make_flow();
+        let bytes = flow.to_compact();
+        println!("{} bytes", bytes.len());
+        let _flow = FlowEntity::from_compact(&bytes).unwrap();
+        assert_eq!(flow, _flow);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let flow = make_flow();
+        let mut bytes = flow.to_compact();
+        assert_eq!(FlowEntity::from_compact(&bytes[..bytes.len() - 1]).err(), Some(CompactError::UnexpectedEnd));
+        bytes[4] = ARENA_VERSION + 1;
+        assert_eq!(FlowEntity::from_compact(&bytes).err(), Some(CompactError::UnknownVersion(ARENA_VERSION + 1)));
+        assert_eq!(FlowEntity::from_compact(b"JSON{}").err(), Some(CompactError::BadMagic));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::{HashMap, HashSet}, fmt::{self, Debug}, hash::Hash};
+use std::{collections::HashMap, fmt::{self, Debug}, hash::Hash};
 
 #[cfg(feature = "serde1")]
 use serde::{Serialize, Deserialize};
@@ -27,7 +27,6 @@ impl<Id, Entity> Node<Id, Entity> {
     }
 }
 
-#[cfg(debug_assertions)]
 impl<Id: Debug, Entity: Debug> Debug for Node<Id, Entity> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct(format!("{:?}", self.id()).as_str())
@@ -57,13 +56,62 @@ pub trait Flow {
 }
 
 
+/// a named top-level tree; its root node has no parent.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde1", derive(Serialize, Deserialize))]
+pub struct Root<Id> {
+    pub name: String,
+    pub id: Id,
+}
+
+/// why a move, or a change to the forest, of a FlowArena was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForestError {
+    /// the id is in the arena already.
+    Occupied,
+    /// another top-level root has the name.
+    NameTaken,
+    /// no node has the id, or no top-level root has the name.
+    Missing,
+    /// the node is root or a top-level root, which only decay takes out.
+    TopLevel,
+    /// the destination isn't root or a top-level root.
+    NotTopLevel,
+    /// the destination lies in the subtree of the moved node.
+    Cycle,
+    /// the place is past the end of the children, or of the forest.
+    OutOfRange,
+}
+
+impl fmt::Display for ForestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ForestError::*;
+        match self {
+            Occupied => write!(f, "the id is in the arena already"),
+            NameTaken => write!(f, "the name is taken by another root"),
+            Missing => write!(f, "no such node or root"),
+            TopLevel => write!(f, "a top-level root can't be moved"),
+            NotTopLevel => write!(f, "the destination is not a top-level root"),
+            Cycle => write!(f, "a node can't move into its own subtree"),
+            OutOfRange => write!(f, "the place is past the end of the children or of the forest"),
+        }
+    }
+}
+
+impl std::error::Error for ForestError {}
+
 #[derive(Clone)]
 #[cfg_attr(debug_assertions, derive(PartialEq, Debug))]
 pub struct FlowArena<Id: Hash + Eq, Entity> {
     /// root: can be a Nil node or a dummy node, but must be in node_map;    
     /// it could contain title info.
     pub root: Id,
+    /// forest: independent top-level trees beside root, ordered; names are unique.
+    pub forest: Vec<Root<Id>>,
     pub node_map: HashMap<Id, Node<Id, Entity>>,
+    /// dormant: stub id -> encoded descendants of the stub, not yet in node_map;
+    /// see lazy.rs.
+    pub dormant: HashMap<Id, Vec<u8>>,
 }
 
 pub type FlowPure<Id> = FlowArena<Id, ()>;
@@ -74,7 +122,7 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> FlowArena
         let root = node.id().clone();
         let mut node_map = HashMap::new();
         node_map.insert(root.clone(), node);
-        FlowArena { root, node_map }
+        FlowArena { root, forest: Vec::new(), node_map, dormant: HashMap::new() }
     }
     /// panics if anything went wrong. Iff in debug state.
     #[cfg(debug_assertions)]
@@ -87,13 +135,16 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> FlowArena
                 assert_eq!(Id::default(), self.root, "! root identical {}", current_str);
                 // root has no parent
                 assert_eq!(node.parent, None, "! root non-parent {}", current_str);
+            } else if self.is_planted(id) {
+                // top-level roots have no parent
+                assert_eq!(node.parent, None, "! forest non-parent {}", current_str);
             } else {
                 // nodes must have parent, except for root
                 assert_ne!(node.parent, None, "! nodes must have parent {}", current_str);
             }
-            // children exist
-            node.children.iter().for_each(|id| {
-                assert!(self.node_map.get(id).is_some(), "! children exist {}", current_str)
+            // children exist, unless dormant
+            node.children.iter().for_each(|child| {
+                assert!(self.node_map.get(child).is_some() || self.dormant.contains_key(id), "! children exist {}", current_str)
             });
             // parent exist
             if let Some(parent_id) = node.parent.clone() {
@@ -104,7 +155,101 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> FlowArena
                 }
             }
         }
+        // stubs exist
+        for id in self.dormant.keys() {
+            assert!(self.node_map.contains_key(id), "! stub exist {:?}", id);
+        }
+        // forest exist and unique
+        for (i, tree) in self.forest.iter().enumerate() {
+            assert!(self.node_map.contains_key(&tree.id), "! forest exist {:?}", tree);
+            assert!(self.forest[..i].iter().all(|x| x.name != tree.name && x.id != tree.id), "! forest unique {:?}", tree);
+        }
     } 
+    fn is_planted(&self, obj: &Id) -> bool {
+        self.forest.iter().any(|tree| tree.id == *obj)
+    }
+    /// whether des is obj or lies in the subtree of obj.
+    fn is_under(&self, des: &Id, obj: &Id) -> bool {
+        let mut cur = Some(des.clone());
+        while let Some(id) = cur {
+            if id == *obj { return true }
+            cur = self.node_map.get(&id).and_then(|node| node.parent.clone());
+        }
+        false
+    }
+    /// plants obj as a new top-level tree named name, after the others; err if obj or name exists.
+    pub fn plant(&mut self, name: &str, obj: Node<Id, Entity>) -> Result<(), ForestError> {
+        self.plant_at(name, obj, self.forest.len())
+    }
+    /// plants obj as the nth top-level tree named name; err if obj or name exists.
+    pub fn plant_at(&mut self, name: &str, mut obj: Node<Id, Entity>, nth: usize) -> Result<(), ForestError> {
+        #[cfg(debug_assertions)]
+        self.check();
+        if self.node_map.contains_key(obj.id()) { return Err(ForestError::Occupied) }
+        if self.root_named(name).is_some() { return Err(ForestError::NameTaken) }
+        if nth > self.forest.len() { return Err(ForestError::OutOfRange) }
+        obj.parent = None;
+        self.forest.insert(nth, Root { name: name.to_string(), id: obj.id.clone() });
+        self.node_map.insert(obj.id.clone(), obj);
+        Ok(())
+    }
+    /// the top-level root named name.
+    pub fn root_named(&self, name: &str) -> Option<&Id> {
+        self.forest.iter().find(|tree| tree.name == name).map(|tree| &tree.id)
+    }
+    /// renames a top-level root; err if old is missing or new is taken.
+    pub fn rename_root(&mut self, old: &str, new: &str) -> Result<(), ForestError> {
+        if old != new && self.root_named(new).is_some() { return Err(ForestError::NameTaken) }
+        let tree = self.forest.iter_mut().find(|tree| tree.name == old).ok_or(ForestError::Missing)?;
+        tree.name = new.to_string();
+        Ok(())
+    }
+    /// walks up from obj to the top of its tree; either root or a planted root.
+    pub fn root_of(&self, obj: &Id) -> Option<&Id> {
+        let mut node = self.node_map.get(obj)?;
+        while let Some(parent) = node.parent.as_ref() {
+            node = self.node_map.get(parent)?;
+        }
+        Some(node.id())
+    }
+    /// moves the subtree of obj to the end of des, where des is root or a planted root;
+    /// err if obj is itself a top-level root.
+    pub fn transplant(&mut self, obj: &Id, des: &Id) -> Result<(), ForestError> {
+        let is_top = |id: &Id| *id == self.root || self.is_planted(id);
+        if !self.node_map.contains_key(obj) { return Err(ForestError::Missing) }
+        if is_top(obj) { return Err(ForestError::TopLevel) }
+        if !is_top(des) { return Err(ForestError::NotTopLevel) }
+        self.cut(obj);
+        if *des == self.root { Ok(()) } else { self.devote_push(obj, des).map_err(|_| ForestError::Missing) }
+    }
+    /// moves the subtree of obj to be the nth child of des, from anywhere below root or a planted root;
+    /// err if obj is a top-level root, or des is missing or lies in the subtree of obj.
+    pub fn relocate(&mut self, obj: &Id, des: &Id, nth: usize) -> Result<(), ForestError> {
+        if !self.node_map.contains_key(obj) { return Err(ForestError::Missing) }
+        if *obj == self.root || self.is_planted(obj) { return Err(ForestError::TopLevel) }
+        if self.is_under(des, obj) { return Err(ForestError::Cycle) }
+        // Note: nth counts the children of des without obj.
+        let len = self.node_map.get(des).map(|node| node.children.iter().filter(|x| *x != obj).count());
+        match len {
+            None => return Err(ForestError::Missing),
+            Some(len) if nth > len => return Err(ForestError::OutOfRange),
+            Some(_) => {}
+        }
+        self.cut(obj);
+        self.devote(obj, des, nth).map_err(|_| ForestError::Missing)
+    }
+    /// cuts obj from its parent and puts it at the end of root, so that obj moves instead of being shared.
+    fn cut(&mut self, obj: &Id) {
+        let parent = self.node_map.get(obj).and_then(|node| node.parent.clone());
+        if let Some(parent) = parent.and_then(|parent| self.node_map.get_mut(&parent)) {
+            parent.children.retain(|x| x != obj);
+        }
+        let root = self.root.clone();
+        if let Some(node) = self.node_map.get_mut(obj) {
+            node.parent = Some(root);
+        }
+        self.root().children.push(obj.clone());
+    }
 }
 
 impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> Flow for FlowArena<Id, Entity> {
@@ -121,7 +266,8 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> Flow for
         self.node_map.get(obj)
     }
     fn grow(&mut self, mut obj: Node<Id, Entity>) -> Result<(), ()> {
-        if cfg!(debug_assertions) { self.check() };
+        #[cfg(debug_assertions)]
+        self.check();
         obj.parent = Some(self.root.clone());
         match self.node_map.get(obj.id()) {
             Some(_) => Err(()),
@@ -134,31 +280,31 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> Flow for
         }
     }
     fn devote(&mut self, obj: &Id, des: &Id, nth: usize) -> Result<(), ()> {
-        if cfg!(debug_assertions) { self.check() };
+        #[cfg(debug_assertions)]
+        self.check();
+        // Note: no cycle.
+        if self.is_under(des, obj) || !self.node_map.contains_key(obj) { return Err(()) }
+        // Note: check everything before any change; nth counts the children of des once obj leaves root.
+        let root = self.root.clone();
+        let len = self.node_map.get(des).map(|owner| {
+            owner.children.iter().filter(|x| *des != root || *x != obj).count()
+        });
+        if len.is_none_or(|len| nth > len) { return Err(()) }
         // Note: no obj in root.
         self.root().children.retain(|x| x != obj);
-        if self.node_map.contains_key(obj) {
-            self.node_map.get_mut(des)
-                .map(|owner| {
-                    if nth <= owner.children.len() {
-                        owner.children.insert(nth, obj.clone());
-                        Some(())
-                    } else {
-                        None
-                    }
-                })
-                .flatten()
-                .map(|x| {
-                    self.node_map.get_mut(obj).map(|obj| {
-                        obj.parent = Some(des.clone());
-                    });
-                    Some(x)
-                })
-                .flatten()
-        } else { None } .ok_or(())
+        // Note: no obj in forest.
+        self.forest.retain(|tree| tree.id != *obj);
+        if let Some(owner) = self.node_map.get_mut(des) {
+            owner.children.insert(nth, obj.clone());
+        }
+        if let Some(obj) = self.node_map.get_mut(obj) {
+            obj.parent = Some(des.clone());
+        }
+        Ok(())
     }
     fn devote_push(&mut self, obj: &Id, des: &Id) -> Result<(), ()> {
-        if cfg!(debug_assertions) { self.check() };
+        #[cfg(debug_assertions)]
+        self.check();
         let nth = self.node_map.get(des).map(|owner| owner.children.len());
         nth.map(|nth| {
             self.devote(obj, des, nth)
@@ -166,39 +312,55 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> Flow for
     }
     /// removes from node_map and purges.
     fn decay(&mut self, obj: &Id) -> Result<(), ()> {
-        if cfg!(debug_assertions) { self.check() };
+        #[cfg(debug_assertions)]
+        self.check();
         self.purge(obj).ok().map(|_|
             self.node_map.remove(obj).map(|_|
                 self.root().children.retain(|rooted| rooted != obj)
             )
         ).flatten().ok_or(())
+        .map(|_| self.forest.retain(|tree| tree.id != *obj))
     }
     /// cuts all the links (except root), but doesn't remove.
     fn purge(&mut self, obj: &Id) -> Result<(), ()> {
-        if cfg!(debug_assertions) { self.check() };
+        #[cfg(debug_assertions)]
+        self.check();
+        // Note: dormant children must be paged in first.
+        if self.dormant.contains_key(obj) { return Err(()) }
         // Note: move children to parent.
         let mut orphan: Vec<Id> = Vec::new();
         let re_owner = self.node_map.get(obj)
             .map_or(None, |x| x.parent.clone())
             .unwrap_or(self.root.clone());
+        let children = self.node_map.get(obj).map(|x| x.children.clone()).unwrap_or_default();
+        let place = self.node_map.get(&re_owner).and_then(|x| x.children.iter().position(|c| c == obj));
         for (_, node) in self.node_map.iter_mut() {
-            let root = self.root.clone();
             node.children.retain(|x| *x != *obj);
             node.parent = node
                 .parent
                 .clone()
-                .and_then(|x| if x == *obj { 
+                .map(|x| if x == *obj { 
                     orphan.push(node.id.clone());
-                    Some(root) 
-                } else { Some(x) });
+                    re_owner.clone()
+                } else { x });
         }
         // must be in root
-        self.root().children.push(obj.clone());
-        self.node_map.get_mut(&dbg!(re_owner)).map(|x| {
-            let mut h: HashSet<Id> = x.children.iter().cloned().collect();
-            h.extend(orphan.into_iter());
-            x.children = h.into_iter().collect();
-        });
+        self.forest.retain(|tree| tree.id != *obj);
+        let root = self.root.clone();
+        if *obj != root {
+            if let Some(node) = self.node_map.get_mut(obj) {
+                node.parent = Some(root);
+            }
+            self.root().children.push(obj.clone());
+        }
+        // Note: orphans take the place of obj, in their order.
+        if let Some(x) = self.node_map.get_mut(&re_owner) {
+            let mut moved: Vec<Id> = children.into_iter().filter(|c| orphan.contains(c)).collect();
+            moved.extend(orphan.into_iter().filter(|c| !moved.contains(c)).collect::<Vec<_>>());
+            moved.retain(|c| !x.children.contains(c));
+            let at = place.unwrap_or(x.children.len()).min(x.children.len());
+            x.children.splice(at..at, moved);
+        }
         Ok(())
     }
 }
@@ -257,6 +419,105 @@ mod tests {
         assert_eq!(flow.root().clone(), Node::default());
     }
 
+    #[test]
+    fn decay_nested() {
+        let mut flow: FlowEntity = FlowArena::new();
+        let obj_vec: Vec<NodeEntity> = (0..4).map(|x| Node::from_id(x.into(), ())).collect();
+        for obj in obj_vec[1..].iter() {
+            flow.grow(obj.clone()).unwrap();
+        }
+        flow.devote_push(obj_vec[2].id(), obj_vec[1].id()).unwrap();
+        flow.devote_push(obj_vec[3].id(), obj_vec[2].id()).unwrap();
+        // the children of a decayed node move up to its parent, not to root
+        flow.decay(obj_vec[2].id()).unwrap();
+        assert_eq!(flow.node(obj_vec[3].id()).unwrap().parent, Some(obj_vec[1].id().clone()));
+        assert_eq!(flow.node(obj_vec[1].id()).unwrap().children, vec![obj_vec[3].id().clone()]);
+        assert_eq!(flow.node_map[&flow.root].children, vec![obj_vec[1].id().clone()]);
+        flow.check();
+    }
+
+    #[test]
+    fn decay_order() {
+        let mut flow: FlowEntity = FlowArena::new();
+        let obj_vec: Vec<NodeEntity> = (0..7).map(|x| Node::from_id(x.into(), ())).collect();
+        for obj in obj_vec[1..].iter() {
+            flow.grow(obj.clone()).unwrap();
+        }
+        for (obj, nth) in [(2, 0), (3, 1), (4, 2)] {
+            flow.relocate(obj_vec[obj].id(), obj_vec[1].id(), nth).unwrap();
+        }
+        for (obj, nth) in [(5, 0), (6, 1)] {
+            flow.relocate(obj_vec[obj].id(), obj_vec[3].id(), nth).unwrap();
+        }
+        // the children take the place of the decayed, in their order
+        flow.decay(obj_vec[3].id()).unwrap();
+        let ids: Vec<EntityId> = [2, 5, 6, 4].iter().map(|&x| obj_vec[x].id().clone()).collect();
+        assert_eq!(flow.node(obj_vec[1].id()).unwrap().children, ids);
+        flow.check();
+    }
+
+    #[test]
+    fn forest() {
+        let mut flow: FlowEntity = FlowArena::new();
+        let obj_vec: Vec<NodeEntity> = (0..8).map(|x| Node::from_id(x.into(), ())).collect();
+        flow.plant("work", obj_vec[1].clone()).unwrap();
+        flow.plant("home", obj_vec[2].clone()).unwrap();
+        assert_eq!(flow.plant("work", obj_vec[3].clone()), Err(ForestError::NameTaken));
+        assert_eq!(flow.plant("else", obj_vec[1].clone()), Err(ForestError::Occupied));
+        assert_eq!(flow.plant_at("else", obj_vec[7].clone(), 3), Err(ForestError::OutOfRange));
+        flow.plant_at("else", obj_vec[7].clone(), 1).unwrap();
+        assert_eq!(flow.forest.iter().map(|tree| tree.name.as_str()).collect::<Vec<_>>(), vec!["work", "else", "home"]);
+        flow.decay(obj_vec[7].id()).unwrap();
+        flow.grow(obj_vec[3].clone()).unwrap();
+        flow.grow(obj_vec[4].clone()).unwrap();
+        flow.devote_push(obj_vec[3].id(), obj_vec[1].id()).unwrap();
+        flow.devote_push(obj_vec[4].id(), obj_vec[3].id()).unwrap();
+        assert_eq!(flow.root_of(obj_vec[4].id()), Some(obj_vec[1].id()));
+        // no cycle
+        assert!(flow.devote_push(obj_vec[1].id(), obj_vec[4].id()).is_err());
+        // a failed devote changes nothing
+        assert!(flow.devote(obj_vec[1].id(), obj_vec[2].id(), 1).is_err());
+        assert!(flow.devote(obj_vec[6].id(), obj_vec[2].id(), 0).is_err());
+        assert_eq!(flow.root_named("work"), Some(obj_vec[1].id()));
+        assert_eq!(flow.node(obj_vec[1].id()).unwrap().parent, None);
+        flow.check();
+        // move 3 (with 4) from work to home
+        flow.transplant(obj_vec[3].id(), obj_vec[2].id()).unwrap();
+        assert_eq!(flow.root_of(obj_vec[4].id()), Some(obj_vec[2].id()));
+        assert!(flow.node(obj_vec[1].id()).unwrap().children.is_empty());
+        assert_eq!(flow.node(obj_vec[2].id()).unwrap().children, vec![obj_vec[3].id().clone()]);
+        // roots can't be transplanted, and only onto roots
+        assert_eq!(flow.transplant(obj_vec[1].id(), obj_vec[2].id()), Err(ForestError::TopLevel));
+        assert_eq!(flow.transplant(obj_vec[4].id(), obj_vec[3].id()), Err(ForestError::NotTopLevel));
+        // relocate moves anywhere below, but never into itself
+        flow.grow(obj_vec[5].clone()).unwrap();
+        flow.relocate(obj_vec[5].id(), obj_vec[4].id(), 0).unwrap();
+        flow.relocate(obj_vec[4].id(), obj_vec[3].id(), 0).unwrap();
+        assert_eq!(flow.relocate(obj_vec[3].id(), obj_vec[5].id(), 0), Err(ForestError::Cycle));
+        assert_eq!(flow.relocate(obj_vec[5].id(), obj_vec[3].id(), 2), Err(ForestError::OutOfRange));
+        flow.relocate(obj_vec[5].id(), obj_vec[3].id(), 1).unwrap();
+        assert_eq!(flow.node(obj_vec[3].id()).unwrap().children, vec![obj_vec[4].id().clone(), obj_vec[5].id().clone()]);
+        flow.relocate(obj_vec[5].id(), &flow.root.clone(), 0).unwrap();
+        assert_eq!(flow.root_of(obj_vec[5].id()), Some(&flow.root));
+        flow.relocate(obj_vec[5].id(), obj_vec[3].id(), 1).unwrap();
+        flow.rename_root("home", "house").unwrap();
+        assert_eq!(flow.root_named("house"), Some(obj_vec[2].id()));
+        // decaying a top-level root leaves its children on root
+        flow.decay(obj_vec[2].id()).unwrap();
+        assert_eq!(flow.root_named("house"), None);
+        assert_eq!(flow.root_of(obj_vec[4].id()), Some(&flow.root));
+        flow.check();
+    }
+
+    #[test]
+    fn serde_without_forest() {
+        let flow = make_flow(false);
+        let mut value = serde_json::to_value(&flow).unwrap();
+        value.as_object_mut().unwrap().remove("forest");
+        let _flow: FlowEntity = serde_json::from_value(value).unwrap();
+        assert_eq!(flow, _flow)
+    }
+
     #[test]
     fn serde() {
         let print_wrapper = |str: &String, aloud: bool| {
@@ -1,4 +1,4 @@
-use super::{Node, FlowArena};
+use super::{Node, FlowArena, Root};
 use std::{fmt::Debug, hash::Hash};
 
 #[derive(Clone)]
@@ -27,6 +27,20 @@ impl<'a, Id, Entity> Iterator for EntitiesMut<'a, Id, Entity> {
     }
 }
 
+#[derive(Clone)]
+pub struct Roots<'a, Id: 'a> {
+    iter: std::slice::Iter<'a, Root<Id>>
+}
+
+impl<'a, Id> Iterator for Roots<'a, Id> {
+    type Item = (&'a str, &'a Id);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.iter.next();
+        x.map(|tree| (tree.name.as_str(), &tree.id))
+    }
+}
+
 impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> FlowArena<Id, Entity> {
     /// returns an iterator over all entities.
     pub fn entities(&self) -> Entities<Id, Entity> {
@@ -40,6 +54,12 @@ impl<Id: Clone + Hash + Eq + Default + Debug, Entity: Default + Debug> FlowArena
             iter: self.node_map.values_mut()
         }
     }
+    /// returns an iterator over the named top-level roots, in order.
+    pub fn roots(&self) -> Roots<'_, Id> {
+        Roots {
+            iter: self.forest.iter()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -94,4 +114,13 @@ mod tests {
         println!("{:?}", entities);
         assert_eq!(entities, flow.node_map.values().map(|x| x.entity.clone()).collect::<Vec<String>>())
     }
+
+    #[test]
+    fn roots() {
+        let mut flow: FlowEntity = make_flow(false);
+        flow.plant("work", Node::from_id(7.into(), String::from("work"))).unwrap();
+        flow.plant("home", Node::from_id(8.into(), String::from("home"))).unwrap();
+        let roots: Vec<(&str, &EntityId)> = flow.roots().collect();
+        assert_eq!(roots, vec![("work", &7.into()), ("home", &8.into())]);
+    }
 }
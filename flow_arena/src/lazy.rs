@@ -0,0 +1,218 @@
+//! lazy.rs contains chunked storage for FlowArena:
+//! 1. a stub is a resident node whose descendants are kept encoded in `dormant`.
+//! 2. page_out / page_out_below encode subtrees; page_in / materialize decode them back.
+//! 3. the compact layout stores chunks as-is, so loading never decodes them.
+//!
+//! chunk layout, with encode_nodes for the nodes and parent 0 standing for the stub:
+//! ```text
+//! n: varint | n * Id | n * node
+//! ```
+//! chunks never nest: paging out a subtree pages its inner stubs in first.
+
+use super::{Node, FlowArena, Compact, CompactError};
+use super::compact::{encode_varint, decode_varint, encode_nodes, decode_nodes};
+use std::{collections::HashMap, hash::Hash};
+
+impl<Id: Compact + Clone + Hash + Eq, Entity: Compact> FlowArena<Id, Entity> {
+    /// whether obj is a stub, i.e. its descendants are dormant.
+    pub fn is_dormant(&self, obj: &Id) -> bool {
+        self.dormant.contains_key(obj)
+    }
+    /// resident descendants of obj, depth first; obj excluded.
+    fn descendants(&self, obj: &Id) -> Vec<&Id> {
+        let mut res = Vec::new();
+        let mut stack: Vec<&Id> = self.node_map.get(obj)
+            .map(|node| node.children.iter().rev().collect())
+            .unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.node_map.get(id) {
+                res.push(node.id());
+                stack.extend(node.children.iter().rev());
+            }
+        }
+        res
+    }
+    /// decodes the dormant descendants of stub into node_map; no-op if stub isn't dormant.
+    /// on error the chunk stays dormant.
+    pub fn page_in(&mut self, stub: &Id) -> Result<(), CompactError> {
+        let chunk = match self.dormant.get(stub) {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+        let mut src = chunk.as_slice();
+        let ids: Vec<Id> = Vec::decode(&mut src)?;
+        let nodes: Vec<Node<Id, Entity>> = decode_nodes(&mut src, &ids, ids.len(), Some(stub.clone()))?;
+        if !src.is_empty() {
+            return Err(CompactError::TrailingBytes(src.len()));
+        }
+        if nodes.iter().any(|node| self.node_map.contains_key(node.id())) {
+            return Err(CompactError::DuplicateId);
+        }
+        self.dormant.remove(stub);
+        self.node_map.extend(nodes.into_iter().map(|node| (node.id().clone(), node)));
+        Ok(())
+    }
+    /// encodes all descendants of obj into a chunk and drops them from node_map,
+    /// leaving obj as a stub; no-op if obj is absent or has no resident descendant.
+    pub fn page_out(&mut self, obj: &Id) -> Result<(), CompactError> {
+        if !self.node_map.contains_key(obj) { return Ok(()) }
+        // Note: no nested chunks; obj itself may be a stub with new resident children.
+        loop {
+            let mut stubs: Vec<Id> = self.descendants(obj).into_iter()
+                .filter(|id| self.dormant.contains_key(*id))
+                .cloned().collect();
+            if self.dormant.contains_key(obj) { stubs.push(obj.clone()) }
+            if stubs.is_empty() { break }
+            for stub in stubs.iter() {
+                self.page_in(stub)?;
+            }
+        }
+        let order = self.descendants(obj);
+        if order.is_empty() { return Ok(()) }
+        let index: HashMap<&Id, usize> = order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut chunk = Vec::new();
+        encode_varint(order.len() as u64, &mut chunk);
+        order.iter().for_each(|id| id.encode(&mut chunk));
+        encode_nodes(&order, &index, &self.node_map, &mut chunk);
+        let order: Vec<Id> = order.into_iter().cloned().collect();
+        for id in order.iter() {
+            self.node_map.remove(id);
+        }
+        self.dormant.insert(obj.clone(), chunk);
+        Ok(())
+    }
+    /// pages out every subtree hanging below depth, counting root and the forest as depth 0.
+    pub fn page_out_below(&mut self, depth: usize) -> Result<(), CompactError> {
+        let mut level: Vec<Id> = vec![self.root.clone()];
+        level.extend(self.forest.iter().map(|tree| tree.id.clone()));
+        for _ in 0..depth {
+            level = level.iter()
+                .filter_map(|id| self.node_map.get(id))
+                .flat_map(|node| node.children.iter().cloned())
+                .collect();
+        }
+        for id in level.iter() {
+            self.page_out(id)?;
+        }
+        Ok(())
+    }
+    /// pages in everything.
+    pub fn page_in_all(&mut self) -> Result<(), CompactError> {
+        let stubs: Vec<Id> = self.dormant.keys().cloned().collect();
+        for stub in stubs.iter() {
+            self.page_in(stub)?;
+        }
+        Ok(())
+    }
+    /// ids held by dormant chunks; only the id tables are decoded.
+    pub fn dormant_ids(&self) -> Result<Vec<Id>, CompactError> {
+        let mut ids = Vec::new();
+        for chunk in self.dormant.values() {
+            let mut src = chunk.as_slice();
+            let len = decode_varint(&mut src)?;
+            for _ in 0..len {
+                ids.push(Id::decode(&mut src)?);
+            }
+        }
+        Ok(ids)
+    }
+    /// makes obj resident, paging in the chunk holding it if needed;
+    /// Ok(false) if obj is nowhere to be found.
+    pub fn materialize(&mut self, obj: &Id) -> Result<bool, CompactError> {
+        if self.node_map.contains_key(obj) { return Ok(true) }
+        let mut holder = None;
+        for (stub, chunk) in self.dormant.iter() {
+            // Note: only the id table is decoded while searching.
+            let mut src = chunk.as_slice();
+            let len = decode_varint(&mut src)?;
+            for _ in 0..len {
+                if Id::decode(&mut src)? == *obj {
+                    holder = Some(stub.clone());
+                    break
+                }
+            }
+            if holder.is_some() { break }
+        }
+        match holder {
+            Some(stub) => self.page_in(&stub).map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Flow;
+    type FlowEntity = FlowArena<u64, String>;
+
+    /// 0 -> 1 -> [2 -> [4, 5], 3 -> [6]]; "notes" -> 7 -> 8
+    fn make_flow() -> FlowEntity {
+        let mut flow: FlowEntity = FlowArena::new();
+        for i in 1..9 {
+            flow.grow(Node::from_id(i, format!(":{}:", i))).ok();
+        }
+        for (obj, des) in [(2, 1), (3, 1), (4, 2), (5, 2), (6, 3), (8, 7)].iter() {
+            flow.devote_push(obj, des).ok();
+        }
+        flow.plant("notes", Node::from_id(9, String::from(":9:"))).ok();
+        flow.devote_push(&7, &9).ok();
+        flow
+    }
+
+    #[test]
+    fn page_out_in() {
+        let full = make_flow();
+        let mut flow = full.clone();
+        flow.page_out(&1).unwrap();
+        assert!(flow.is_dormant(&1));
+        assert_eq!(flow.node_map.len(), full.node_map.len() - 5);
+        flow.check();
+        // structural changes on stubs are refused
+        assert!(flow.decay(&1).is_err());
+        flow.page_in(&1).unwrap();
+        assert_eq!(flow, full);
+    }
+
+    #[test]
+    fn nested() {
+        let full = make_flow();
+        let mut flow = full.clone();
+        flow.page_out(&2).unwrap();
+        flow.page_out(&1).unwrap();
+        assert_eq!(flow.dormant.len(), 1);
+        flow.page_in_all().unwrap();
+        assert_eq!(flow, full);
+    }
+
+    #[test]
+    fn below_and_materialize() {
+        let full = make_flow();
+        let mut flow = full.clone();
+        flow.page_out_below(1).unwrap();
+        // depth 1: 1, 7; resident: 0, 1, 7, 9 and the other grown nodes on root
+        assert!(flow.is_dormant(&1) && flow.is_dormant(&7));
+        assert!(flow.node(&4).is_none());
+        let mut ids = flow.dormant_ids().unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![2, 3, 4, 5, 6, 8]);
+        assert!(flow.node(&4).is_none());
+        assert_eq!(flow.materialize(&4), Ok(true));
+        assert_eq!(flow.node(&4).map(|node| node.entity.as_str()), Some(":4:"));
+        assert_eq!(flow.materialize(&42), Ok(false));
+        flow.page_in_all().unwrap();
+        assert_eq!(flow, full);
+    }
+
+    #[test]
+    fn compact_keeps_chunks() {
+        let full = make_flow();
+        let mut flow = full.clone();
+        flow.page_out_below(0).unwrap();
+        let bytes = flow.to_compact();
+        let mut _flow = FlowEntity::from_compact(&bytes).unwrap();
+        assert_eq!(_flow, flow);
+        _flow.page_in_all().unwrap();
+        assert_eq!(_flow, full);
+    }
+}
@@ -1,5 +1,8 @@
 mod flow;
 mod ser_de;
 mod iter;
+pub mod compact;
+mod lazy;
 
-pub use flow::{Node, Flow, FlowArena, FlowPure};
+pub use flow::{Node, Flow, FlowArena, FlowPure, Root, ForestError};
+pub use compact::{Compact, CompactError};
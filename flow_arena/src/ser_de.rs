@@ -1,4 +1,4 @@
-use super::{Node, FlowArena};
+use super::{Node, FlowArena, Root};
 use std::{fmt, hash::Hash};
 
 #[cfg(feature = "serde1")]
@@ -6,10 +6,13 @@ use serde::ser::{Serialize, Serializer, SerializeStruct};
 #[cfg(feature = "serde1")]
 impl<Id: Serialize + Hash + Eq, Entity: Serialize> Serialize for FlowArena<Id, Entity> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut flow = serializer.serialize_struct("Flow", 2)?;
+        let mut flow = serializer.serialize_struct("Flow", 4)?;
         flow.serialize_field("root", &self.root)?;
         let seq: Vec<&Node<Id, Entity>> = self.node_map.values().collect();
         flow.serialize_field("node_map", &seq)?;
+        flow.serialize_field("forest", &self.forest)?;
+        let dormant: Vec<(&Id, &Vec<u8>)> = self.dormant.iter().collect();
+        flow.serialize_field("dormant", &dormant)?;
         flow.end()
     }
 }
@@ -21,7 +24,7 @@ use std::marker::PhantomData;
 #[cfg(feature = "serde1")]
 impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> Deserialize<'de> for FlowArena<Id, Entity> {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        enum Field { Root, NodeMap }
+        enum Field { Root, Forest, NodeMap, Dormant }
         impl<'de> Deserialize<'de> for Field {
             fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Field, D::Error> {
                 struct FieldVisitor;
@@ -30,7 +33,7 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter.write_str("`root` or `node_map`")
+                        formatter.write_str("`root`, `node_map`, `forest` or `dormant`")
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -39,6 +42,8 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                     {
                         match value {
                             "root" => Ok(Field::Root),
+                            "forest" => Ok(Field::Forest),
+                            "dormant" => Ok(Field::Dormant),
                             "node_map" => Ok(Field::NodeMap),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
@@ -69,7 +74,11 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                 let node_vec: Vec<Node<Id, Entity>> = seq.next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
                 let node_map = node_vec.into_iter().map(|node| (node.id().clone(), node)).collect();
-                Ok(Self::Value { root, node_map })
+                // Note: forest and dormant are absent in data written before they existed.
+                let forest: Vec<Root<Id>> = seq.next_element()?.unwrap_or_default();
+                let dormant: Vec<(Id, Vec<u8>)> = seq.next_element()?.unwrap_or_default();
+                let dormant = dormant.into_iter().collect();
+                Ok(Self::Value { root, forest, node_map, dormant })
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
@@ -77,7 +86,9 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                 V: MapAccess<'de>,
             {
                 let mut root = None;
+                let mut forest = None;
                 let mut node_map = None;
+                let mut dormant = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Root => {
@@ -86,6 +97,19 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                             }
                             root = Some(map.next_value()?);
                         }
+                        Field::Forest => {
+                            if forest.is_some() {
+                                return Err(de::Error::duplicate_field("forest"));
+                            }
+                            forest = Some(map.next_value()?);
+                        }
+                        Field::Dormant => {
+                            if dormant.is_some() {
+                                return Err(de::Error::duplicate_field("dormant"));
+                            }
+                            let dormant_vec: Vec<(Id, Vec<u8>)> = map.next_value()?;
+                            dormant = Some(dormant_vec.into_iter().collect());
+                        }
                         Field::NodeMap => {
                             if node_map.is_some() {
                                 return Err(de::Error::duplicate_field("node_map"));
@@ -97,11 +121,14 @@ impl<'de, Id: Deserialize<'de> + Clone + Hash + Eq, Entity: Deserialize<'de>> De
                 }
                 let root = root.ok_or_else(|| de::Error::missing_field("root"))?;
                 let node_map = node_map.ok_or_else(|| de::Error::missing_field("node_map"))?;
-                Ok(Self::Value { root, node_map })
+                // Note: forest and dormant are absent in data written before they existed.
+                let forest = forest.unwrap_or_default();
+                let dormant = dormant.unwrap_or_default();
+                Ok(Self::Value { root, forest, node_map, dormant })
             }
         }
 
-        const FIELDS: &'static [&'static str] = &["root", "node_map"];
+        const FIELDS: &'static [&'static str] = &["root", "node_map", "forest", "dormant"];
         deserializer.deserialize_struct("Flow", FIELDS, FlowVisitor { marker: PhantomData })
     }
 }
\ No newline at end of file
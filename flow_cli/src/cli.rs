@@ -0,0 +1,124 @@
+//! cli.rs contains the command line, as parsed by clap.
+
+use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// flow.er in the terminal: a tree of entities kept in a local vessel file.
+#[derive(Debug, Parser)]
+#[command(name = "flow", version)]
+pub struct Cli {
+    /// the vessel file.
+    #[arg(long, short, global = true, env = "FLOW_FILE", default_value = "flow.json")]
+    pub file: PathBuf,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// creates an empty vessel file.
+    Init {
+        /// overwrites an existing file.
+        #[arg(long)]
+        force: bool,
+    },
+    /// adds an entity and prints its id.
+    Add {
+        face: String,
+        /// the parent; top level if left out.
+        #[arg(long)]
+        under: Option<String>,
+    },
+    /// lists the children of an entity, or the top level.
+    Ls {
+        id: Option<String>,
+    },
+    /// prints an entity and its subtree, or everything.
+    Tree {
+        id: Option<String>,
+        /// levels to show below the start.
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// moves an entity with its subtree.
+    Mv {
+        id: String,
+        /// the new parent; top level if left out.
+        #[arg(long)]
+        under: Option<String>,
+    },
+    /// moves an entity to the trash; its children move up unless -r.
+    Rm {
+        id: String,
+        /// removes the whole subtree.
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// sets the status of an entity, e.g. `status 3fa2 done`.
+    Status {
+        id: String,
+        status: Status,
+    },
+    /// adds tags to an entity, or prints them if none are given.
+    Tag {
+        id: String,
+        tags: Vec<String>,
+        /// removes the tags instead.
+        #[arg(short, long)]
+        delete: bool,
+    },
+    /// full-text search over faces and bubbles.
+    Search {
+        query: Vec<String>,
+        /// searches the archive instead.
+        #[arg(long)]
+        archive: bool,
+    },
+    /// prints an entity and its subtree, or everything, in another format.
+    Export {
+        id: Option<String>,
+        #[arg(long, value_enum, default_value = "markdown")]
+        format: Format,
+    },
+    /// opens the interactive outliner; every change is saved right away.
+    Tui,
+    /// moves every subtree that is all done into the archive, or lists the archive.
+    Archive {
+        /// only what was done at least this many days ago.
+        #[arg(long)]
+        older_than: Option<u64>,
+        /// lists the archived subtrees instead.
+        #[arg(long)]
+        list: bool,
+    },
+    /// puts an archived subtree back where it was.
+    Unarchive {
+        id: String,
+    },
+    /// lists the trash, oldest first; entries are purged after 30 days.
+    Trash,
+    /// puts a trashed entity back where it was, with its subtree.
+    Restore {
+        id: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Status {
+    New,
+    Planning,
+    Pending,
+    Marching,
+    Done,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[value(alias = "md")]
+    Markdown,
+    Opml,
+    #[value(name = "todotxt", alias = "todo.txt")]
+    TodoTxt,
+    Ics,
+    Json,
+}
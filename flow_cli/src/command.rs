@@ -0,0 +1,404 @@
+//! command.rs runs a parsed command against the vessel file:
+//! the file is loaded, changed, and saved back only if the command edits anything.
+//!
+//! ids are printed as the first 7 hex digits of their unique part, and any unique prefix resolves.
+
+use std::{fmt, io::{self, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+use flow_vessel::{
+    ArchiveError, EntityId, ForestError, MarkdownOptions, PersistError, ProcessStatus, ResolveError, SearchHit, TagSet,
+    TransitError, TrashError, Vessel,
+};
+
+use super::cli::{Cli, Command, Format, Status};
+use super::tui;
+
+const SHORT: usize = 7;
+
+#[derive(Debug)]
+pub enum CliError {
+    Io(io::Error),
+    Persist(PersistError),
+    /// the vessel file doesn't exist yet.
+    Missing(PathBuf),
+    /// init found a vessel file already.
+    Exists(PathBuf),
+    Resolve(ResolveError),
+    Transit(TransitError),
+    Archive(ArchiveError),
+    Trash(TrashError),
+    /// obj can't be moved under des, e.g. into its own subtree.
+    Move { obj: String, des: String, why: ForestError },
+    Usage(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CliError::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            Persist(e) => write!(f, "{}", e),
+            Missing(path) => write!(f, "no vessel at {}; run `flow init` first", path.display()),
+            Exists(path) => write!(f, "{} exists; pass --force to overwrite it", path.display()),
+            Resolve(e) => write!(f, "{}", e),
+            Transit(e) => write!(f, "{}", e),
+            Archive(e) => write!(f, "{}", e),
+            Trash(e) => write!(f, "{}", e),
+            Move { obj, des, why } => write!(f, "can't move {} under {}: {}", obj, des, why),
+            Usage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Io(e)
+    }
+}
+
+impl From<PersistError> for CliError {
+    fn from(e: PersistError) -> Self {
+        CliError::Persist(e)
+    }
+}
+
+impl From<ResolveError> for CliError {
+    fn from(e: ResolveError) -> Self {
+        CliError::Resolve(e)
+    }
+}
+
+impl From<TransitError> for CliError {
+    fn from(e: TransitError) -> Self {
+        CliError::Transit(e)
+    }
+}
+
+impl From<ArchiveError> for CliError {
+    fn from(e: ArchiveError) -> Self {
+        CliError::Archive(e)
+    }
+}
+
+impl From<TrashError> for CliError {
+    fn from(e: TrashError) -> Self {
+        CliError::Trash(e)
+    }
+}
+
+impl From<Status> for ProcessStatus {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::New => ProcessStatus::New,
+            Status::Planning => ProcessStatus::Planning,
+            Status::Pending => ProcessStatus::Pending,
+            Status::Marching => ProcessStatus::Marching,
+            Status::Done => ProcessStatus::Done,
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Vessel, CliError> {
+    match Vessel::load(path) {
+        Err(PersistError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Err(CliError::Missing(path.to_path_buf())),
+        res => Ok(res?),
+    }
+}
+
+fn short(id: &EntityId) -> String {
+    format!("{:x}", id.unique()).chars().take(SHORT).collect()
+}
+
+/// `<short id> [x] face (Status) #tags`, as in the markdown export.
+fn describe(id: &EntityId, face: &str, process: ProcessStatus, tags: &TagSet) -> String {
+    let check = if process == ProcessStatus::Done { "x" } else { " " };
+    let mut line = format!("{:<width$} [{}] {}", short(id), check, face.split_whitespace().collect::<Vec<_>>().join(" "), width = SHORT);
+    if !matches!(process, ProcessStatus::New | ProcessStatus::Done) {
+        line.push_str(&format!(" ({})", process.type_str()));
+    }
+    for tag in tags.into_iter() {
+        line.push_str(&format!(" #{}", tag));
+    }
+    line
+}
+
+fn line(vessel: &mut Vessel, id: &EntityId) -> String {
+    match vessel.entity_get(id) {
+        Some(entity) => describe(id, &entity.face, entity.process, &entity.tags),
+        None => short(id),
+    }
+}
+
+/// as line, for an entity in the archive.
+fn archived_line(vessel: &Vessel, id: &EntityId) -> String {
+    match vessel.archive_get(id) {
+        Some(entity) => describe(id, &entity.face, entity.process, &entity.tags),
+        None => short(id),
+    }
+}
+
+/// the face of an entity in the tree, the archive or the trash, or on the recorded path of an archived subtree.
+fn face_of(vessel: &mut Vessel, id: &EntityId) -> Option<String> {
+    if let Some(entity) = vessel.archive_get(id) {
+        return Some(entity.face.clone())
+    }
+    let recorded = vessel.archive_list().iter().flat_map(|entry| entry.path().iter())
+        .find(|(x, _)| x == id)
+        .map(|(_, face)| face.clone());
+    let trashed = vessel.trash_list().iter().find(|entry| entry.id() == id).map(|entry| entry.face().to_string());
+    recorded.or(trashed).or_else(|| vessel.entity_get(id).map(|entity| entity.face.clone()))
+}
+
+/// `  in a / b` for the path of a hit; empty at the top level.
+fn within(faces: Vec<String>) -> String {
+    if faces.is_empty() { String::new() } else { format!("  in {}", faces.join(" / ")) }
+}
+
+fn tree(vessel: &mut Vessel, id: &EntityId, depth: usize, max: Option<usize>, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}{}", "  ".repeat(depth), line(vessel, id))?;
+    if max.is_some_and(|max| depth >= max) { return Ok(()) }
+    for child in vessel.entity_children(id) {
+        tree(vessel, &child, depth + 1, max, out)?;
+    }
+    Ok(())
+}
+
+/// runs cli, writing what it prints to out.
+pub fn run(cli: Cli, out: &mut dyn Write) -> Result<(), CliError> {
+    let path = cli.file;
+    if let Command::Init { force } = cli.command {
+        if path.exists() && !force {
+            return Err(CliError::Exists(path));
+        }
+        Vessel::new().save(&path)?;
+        writeln!(out, "initialized {}", path.display())?;
+        return Ok(())
+    }
+    let mut vessel = load(&path)?;
+    let resolve = |vessel: &mut Vessel, id: Option<String>| -> Result<Option<EntityId>, CliError> {
+        id.map(|id| vessel.resolve_prefix(&id)).transpose().map_err(CliError::from)
+    };
+    let edited = match cli.command {
+        Command::Init { .. } => unreachable!("handled above"),
+        Command::Add { face, under } => {
+            let under = resolve(&mut vessel, under)?;
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).expect("just grown").face = face;
+            vessel.entity_move(&id, under.as_ref()).expect("under exists");
+            writeln!(out, "{}", short(&id))?;
+            true
+        }
+        Command::Ls { id } => {
+            let ids = match resolve(&mut vessel, id)? {
+                Some(id) => vessel.entity_children(&id),
+                None => vessel.entity_top(),
+            };
+            for id in ids.iter() {
+                writeln!(out, "{}", line(&mut vessel, id))?;
+            }
+            false
+        }
+        Command::Tree { id, depth } => {
+            let ids = match resolve(&mut vessel, id)? {
+                Some(id) => vec![id],
+                None => vessel.entity_top(),
+            };
+            for id in ids.iter() {
+                tree(&mut vessel, id, 0, depth, out)?;
+            }
+            false
+        }
+        Command::Mv { id, under } => {
+            let obj = resolve(&mut vessel, Some(id))?.expect("given");
+            let des = resolve(&mut vessel, under)?;
+            vessel.entity_move(&obj, des.as_ref()).map_err(|why| CliError::Move {
+                obj: short(&obj),
+                des: des.as_ref().map_or("the top level".to_string(), short),
+                why,
+            })?;
+            true
+        }
+        Command::Rm { id, recursive } => {
+            let id = resolve(&mut vessel, Some(id))?.expect("given");
+            if recursive { vessel.entity_trash(&id) } else { vessel.entity_trash_alone(&id) }
+        }
+        Command::Status { id, status } => {
+            let id = resolve(&mut vessel, Some(id))?.expect("given");
+            vessel.entity_transit(&id, status.into())?;
+            true
+        }
+        Command::Tag { id, tags, delete } => {
+            let id = resolve(&mut vessel, Some(id))?.expect("given");
+            if tags.is_empty() {
+                for tag in vessel.entity_get(&id).expect("resolved").tags.into_iter() {
+                    writeln!(out, "{}", tag)?;
+                }
+                false
+            } else {
+                let entity = vessel.entity_get_mut(&id).expect("resolved");
+                for tag in tags {
+                    if delete { entity.tags.remove(tag); } else { entity.tags.push(tag); }
+                }
+                true
+            }
+        }
+        Command::Search { query, archive } => {
+            let query = query.join(" ");
+            let hits: Vec<SearchHit> = if archive { vessel.archive_search(&query) } else { vessel.search(&query) };
+            for hit in hits {
+                let text = if archive { archived_line(&vessel, &hit.id) } else { line(&mut vessel, &hit.id) };
+                let path = hit.path.iter().filter_map(|id| face_of(&mut vessel, id)).collect();
+                writeln!(out, "{}{}", text, within(path))?;
+            }
+            false
+        }
+        Command::Export { id, format } => {
+            let id = resolve(&mut vessel, id)?;
+            let text = match format {
+                Format::Markdown => vessel.export_markdown(id.as_ref(), &MarkdownOptions { status: true, ..MarkdownOptions::default() }),
+                Format::Opml => vessel.export_opml(id.as_ref(), "flow"),
+                Format::TodoTxt => vessel.export_todotxt(id.as_ref()),
+                Format::Ics => vessel.export_ics(id.as_ref(), SystemTime::now()),
+                Format::Json if id.is_some() => return Err(CliError::Usage("json exports the whole vessel; leave out the id".to_string())),
+                Format::Json => vessel.to_json()?,
+            };
+            write!(out, "{}", text)?;
+            false
+        }
+        Command::Tui => {
+            tui::run(&mut vessel, &path)?;
+            false
+        }
+        Command::Archive { older_than, list: true } => {
+            if older_than.is_some() {
+                return Err(CliError::Usage("--older-than archives; leave it out to list".to_string()))
+            }
+            for entry in vessel.archive_list() {
+                let faces = entry.path().iter().map(|(_, face)| face.clone()).collect();
+                writeln!(out, "{}{}", archived_line(&vessel, entry.id()), within(faces))?;
+            }
+            false
+        }
+        Command::Archive { older_than, list: false } => {
+            let older_than = older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+            let ids = vessel.archive_done(older_than, SystemTime::now());
+            for id in ids.iter() {
+                writeln!(out, "{}", archived_line(&vessel, id))?;
+            }
+            !ids.is_empty()
+        }
+        Command::Unarchive { id } => {
+            let id = vessel.archive_resolve(&id)?;
+            vessel.entity_unarchive(&id)?;
+            true
+        }
+        Command::Trash => {
+            for entry in vessel.trash_list().to_vec() {
+                let entity = entry.entity();
+                let faces = entry.parent().and_then(|parent| face_of(&mut vessel, parent)).into_iter().collect();
+                writeln!(out, "{}{}", describe(entry.id(), &entity.face, entity.process, &entity.tags), within(faces))?;
+            }
+            false
+        }
+        Command::Restore { id } => {
+            let id = vessel.trash_resolve(&id)?;
+            vessel.trash_restore(&id)?;
+            true
+        }
+    };
+    if edited {
+        vessel.save(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    struct Flow {
+        dir: PathBuf,
+    }
+
+    impl Flow {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("flow_cli-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Flow { dir }
+        }
+        fn run(&self, args: &[&str]) -> Result<String, CliError> {
+            let file = self.dir.join("flow.json");
+            let mut argv = vec!["flow", "--file", file.to_str().unwrap()];
+            argv.extend_from_slice(args);
+            let cli = Cli::try_parse_from(argv).map_err(|e| CliError::Usage(e.to_string()))?;
+            let mut out = Vec::new();
+            run(cli, &mut out)?;
+            Ok(String::from_utf8(out).unwrap())
+        }
+        fn ok(&self, args: &[&str]) -> String {
+            self.run(args).unwrap_or_else(|e| panic!("{:?}: {}", args, e))
+        }
+    }
+
+    impl Drop for Flow {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    #[test]
+    fn session() {
+        let flow = Flow::new("session");
+        assert!(matches!(flow.run(&["ls"]), Err(CliError::Missing(_))));
+        flow.ok(&["init"]);
+        assert!(matches!(flow.run(&["init"]), Err(CliError::Exists(_))));
+
+        let report = flow.ok(&["add", "Write report"]).trim().to_string();
+        let numbers = flow.ok(&["add", "Collect numbers", "--under", &report]).trim().to_string();
+        let review = flow.ok(&["add", "Review"]).trim().to_string();
+        flow.ok(&["status", &numbers[..4], "done"]);
+        flow.ok(&["status", &report, "marching"]);
+        flow.ok(&["tag", &report, "work/report", "urgent"]);
+        flow.ok(&["tag", &report, "urgent", "-d"]);
+        assert_eq!(flow.ok(&["tag", &report]), "work/report\n");
+        assert_eq!(flow.ok(&["tree"]), format!("\
+{} [ ] Write report (Marching) #work/report
+  {} [x] Collect numbers
+{} [ ] Review
+", report, numbers, review));
+        assert_eq!(flow.ok(&["ls", &report]), format!("{} [x] Collect numbers\n", numbers));
+        assert_eq!(flow.ok(&["tree", "--depth", "0"]).lines().count(), 2);
+        assert!(flow.ok(&["search", "numbers"]).ends_with("  in Write report\n"));
+        assert!(flow.ok(&["export", &report, "--format", "md"]).contains("  - [x] Collect numbers\n"));
+        assert!(flow.ok(&["export", "--format", "opml"]).contains("<outline text=\"Review\"/>"));
+        assert!(flow.ok(&["export", "--format", "todotxt"]).contains("Collect numbers +Writereport"));
+
+        // archived while done, and back
+        assert_eq!(flow.ok(&["archive"]), format!("{} [x] Collect numbers\n", numbers));
+        assert_eq!(flow.ok(&["ls", &report]), "");
+        assert_eq!(flow.ok(&["archive", "--list"]), format!("{} [x] Collect numbers  in Write report\n", numbers));
+        assert_eq!(flow.ok(&["search", "--archive", "numbers"]), format!("{} [x] Collect numbers  in Write report\n", numbers));
+        assert_eq!(flow.ok(&["archive"]), "");
+        flow.ok(&["unarchive", &numbers[..5]]);
+        assert_eq!(flow.ok(&["ls", &report]), format!("{} [x] Collect numbers\n", numbers));
+        assert!(matches!(flow.run(&["unarchive", &numbers]), Err(CliError::Resolve(ResolveError::NotFound(_)))));
+
+        flow.ok(&["mv", &report, "--under", &review]);
+        assert!(matches!(flow.run(&["mv", &review, "--under", &numbers]), Err(CliError::Move { .. })));
+        assert_eq!(flow.ok(&["ls"]), format!("{} [ ] Review\n", review));
+        flow.ok(&["rm", &report]);
+        assert_eq!(flow.ok(&["ls", &review]), format!("{} [x] Collect numbers\n", numbers));
+        flow.ok(&["rm", "-r", &review]);
+        assert_eq!(flow.ok(&["ls"]), "");
+
+        // kept in the trash, and restored with the subtree
+        assert_eq!(flow.ok(&["trash"]), format!("{} [ ] Write report (Marching) #work/report  in Review\n{} [ ] Review\n", report, review));
+        flow.ok(&["restore", &review[..5]]);
+        assert_eq!(flow.ok(&["ls", &review]), format!("{} [x] Collect numbers\n", numbers));
+        assert!(matches!(flow.run(&["restore", &review]), Err(CliError::Resolve(ResolveError::NotFound(_)))));
+        assert!(matches!(flow.run(&["rm", "ffff"]), Err(CliError::Resolve(ResolveError::NotFound(_)))));
+        assert!(matches!(flow.run(&["status", "x", "finished"]), Err(CliError::Usage(_))));
+    }
+}
@@ -0,0 +1,14 @@
+mod cli;
+mod command;
+mod tui;
+
+use clap::Parser;
+
+fn main() {
+    let cli = cli::Cli::parse();
+    let stdout = std::io::stdout();
+    if let Err(e) = command::run(cli, &mut stdout.lock()) {
+        eprintln!("flow: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,357 @@
+//! tui.rs contains `flow tui`, an outliner after the `Linear` view of the browser:
+//! one row per entity in tree order, a cursor that wanders up and down, and every edit saved at once.
+//!
+//! keys: ↑/↓ wander, ctrl+↑/↓ move the entity among its siblings, enter adds a sibling below,
+//! tab / shift+tab indent and outdent, e or i edits the face, space cycles the status, q quits.
+//! while editing, enter adds the next sibling, esc stops, and backspace on an empty face removes the entity.
+
+use std::{io, path::{Path, PathBuf}};
+use flow_vessel::{EntityId, ForestError, ProcessStatus, Vessel};
+use ratatui::{
+    backend::Backend,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+
+use super::command::CliError;
+
+const HELP: &str = "↑↓ wander  enter add  tab/⇧tab indent  e edit  space status  q quit";
+
+pub struct Outliner<'a> {
+    vessel: &'a mut Vessel,
+    path: PathBuf,
+    /// entities in tree order, with their depth.
+    rows: Vec<(EntityId, usize)>,
+    pos: usize,
+    /// the face being typed, if editing the row at pos.
+    edit: Option<String>,
+    message: String,
+    list: ListState,
+}
+
+/// `[x] face (Status) #tags`, indented by depth.
+fn label(vessel: &mut Vessel, id: &EntityId, depth: usize) -> String {
+    let mut line = "  ".repeat(depth);
+    let entity = match vessel.entity_get(id) {
+        Some(entity) => entity,
+        None => return line,
+    };
+    let check = if entity.process == ProcessStatus::Done { "x" } else { " " };
+    line.push_str(&format!("[{}] {}", check, entity.face.split_whitespace().collect::<Vec<_>>().join(" ")));
+    if !matches!(entity.process, ProcessStatus::New | ProcessStatus::Done) {
+        line.push_str(&format!(" ({})", entity.process.type_str()));
+    }
+    for tag in entity.tags.into_iter() {
+        line.push_str(&format!(" #{}", tag));
+    }
+    line
+}
+
+impl<'a> Outliner<'a> {
+    /// edits vessel, saving it to path after each change.
+    pub fn new(vessel: &'a mut Vessel, path: &Path) -> Self {
+        let mut outliner = Outliner {
+            vessel,
+            path: path.to_path_buf(),
+            rows: Vec::new(),
+            pos: 0,
+            edit: None,
+            message: String::new(),
+            list: ListState::default(),
+        };
+        outliner.refresh(None);
+        outliner
+    }
+    /// rebuilds the rows, keeping the cursor on id if given.
+    fn refresh(&mut self, id: Option<&EntityId>) {
+        let mut rows = Vec::new();
+        let mut stack: Vec<(EntityId, usize)> = self.vessel.entity_top().into_iter().rev().map(|id| (id, 0)).collect();
+        while let Some((id, depth)) = stack.pop() {
+            stack.extend(self.vessel.entity_children(&id).into_iter().rev().map(|child| (child, depth + 1)));
+            rows.push((id, depth));
+        }
+        self.rows = rows;
+        if let Some(pos) = id.and_then(|id| self.rows.iter().position(|(x, _)| x == id)) {
+            self.pos = pos;
+        }
+        self.pos = self.pos.min(self.rows.len().saturating_sub(1));
+    }
+    fn current(&self) -> Option<EntityId> {
+        self.rows.get(self.pos).map(|(id, _)| id.clone())
+    }
+    /// the parent of id, its siblings and its place among them.
+    fn siblings(&mut self, id: &EntityId) -> (Option<EntityId>, Vec<EntityId>, usize) {
+        let parent = self.vessel.entity_parent(id);
+        let siblings = match &parent {
+            Some(parent) => self.vessel.entity_children(parent),
+            None => self.vessel.entity_top(),
+        };
+        let nth = siblings.iter().position(|x| x == id).unwrap_or(siblings.len());
+        (parent, siblings, nth)
+    }
+    fn save(&mut self) {
+        if let Err(e) = self.vessel.save(&self.path) {
+            self.message = format!("can't save: {}", e);
+        }
+    }
+    /// moves the cursor by delta, clamped, as Dancer::wander does.
+    fn wander(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1) as isize;
+        self.pos = (self.pos as isize + delta).clamp(0, last) as usize;
+    }
+    /// saves a move of id, or says why it failed.
+    fn moved(&mut self, id: &EntityId, res: Result<(), ForestError>, what: &str) {
+        match res {
+            Ok(()) => {
+                self.save();
+                self.refresh(Some(id));
+            }
+            Err(e) => self.message = format!("can't {} here: {}", what, e),
+        }
+    }
+    /// moves the current entity by delta among its siblings.
+    fn shift(&mut self, delta: isize) {
+        let id = match self.current() { Some(id) => id, None => return };
+        let (parent, _, nth) = self.siblings(&id);
+        if nth == 0 && delta < 0 { return }
+        let res = self.vessel.entity_move_at(&id, parent.as_ref(), (nth as isize + delta) as usize);
+        self.moved(&id, res, "move");
+    }
+    /// makes the current entity the last child of its previous sibling.
+    fn indent(&mut self) {
+        let id = match self.current() { Some(id) => id, None => return };
+        let (_, siblings, nth) = self.siblings(&id);
+        if nth == 0 { return }
+        let res = self.vessel.entity_move_at(&id, Some(&siblings[nth - 1]), usize::MAX);
+        self.moved(&id, res, "indent");
+    }
+    /// moves the current entity right after its parent.
+    fn outdent(&mut self) {
+        let id = match self.current() { Some(id) => id, None => return };
+        let parent = match self.vessel.entity_parent(&id) { Some(parent) => parent, None => return };
+        let (grand, _, nth) = self.siblings(&parent);
+        let res = self.vessel.entity_move_at(&id, grand.as_ref(), nth + 1);
+        self.moved(&id, res, "outdent");
+    }
+    /// adds a sibling below the cursor, or the first entity, and starts editing it.
+    fn add(&mut self) {
+        let id = self.vessel.entity_grow();
+        if let Some(current) = self.current() {
+            let (parent, _, nth) = self.siblings(&current);
+            self.vessel.entity_move_at(&id, parent.as_ref(), nth + 1).ok();
+        }
+        self.save();
+        self.refresh(Some(&id));
+        self.edit = Some(String::new());
+    }
+    /// sets the current entity to the next status the transition table permits.
+    fn cycle(&mut self) {
+        let id = match self.current() { Some(id) => id, None => return };
+        let from = match self.vessel.entity_get(&id) { Some(entity) => entity.process, None => return };
+        let all = ProcessStatus::vec_all();
+        let vessel = &mut *self.vessel;
+        let to = (1..all.len()).map(|k| all[(from.order() + k) % all.len()])
+            .find(|to| vessel.entity_transit(&id, *to).is_ok());
+        match to {
+            Some(_) => self.save(),
+            None => self.message = format!("{} can't change", from.type_str()),
+        }
+    }
+    fn start_edit(&mut self) {
+        if let Some(id) = self.current() {
+            self.edit = self.vessel.entity_get(&id).map(|entity| entity.face.clone());
+        }
+    }
+    /// writes the face being typed, if any.
+    fn commit(&mut self) {
+        if let (Some(face), Some(id)) = (self.edit.take(), self.current()) {
+            if let Some(entity) = self.vessel.entity_get_mut(&id) {
+                entity.face = face;
+            }
+            self.save();
+        }
+    }
+    /// moves the current entity to the trash; its children move up.
+    fn erase(&mut self) {
+        self.edit = None;
+        if let Some(id) = self.current() {
+            self.vessel.entity_trash_alone(&id);
+            self.save();
+            self.wander(-1);
+            self.refresh(None);
+        }
+    }
+    /// applies a key; false once the outliner should close.
+    pub fn handle(&mut self, key: KeyEvent) -> bool {
+        if key.kind != KeyEventKind::Press { return true }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && key.code == KeyCode::Char('c') {
+            self.commit();
+            return false
+        }
+        self.message.clear();
+        if let Some(face) = self.edit.as_mut() {
+            match key.code {
+                KeyCode::Char(c) => face.push(c),
+                KeyCode::Backspace if face.is_empty() => self.erase(),
+                KeyCode::Backspace => { face.pop(); }
+                KeyCode::Enter => {
+                    self.commit();
+                    self.add();
+                }
+                KeyCode::Esc => self.commit(),
+                KeyCode::Tab => self.indent(),
+                KeyCode::BackTab => self.outdent(),
+                KeyCode::Up => {
+                    self.commit();
+                    self.wander(-1);
+                }
+                KeyCode::Down => {
+                    self.commit();
+                    self.wander(1);
+                }
+                _ => {}
+            }
+            return true
+        }
+        match (ctrl, key.code) {
+            (false, KeyCode::Up) => self.wander(-1),
+            (false, KeyCode::Down) => self.wander(1),
+            (true, KeyCode::Up) => self.shift(-1),
+            (true, KeyCode::Down) => self.shift(1),
+            (_, KeyCode::Enter) => self.add(),
+            (_, KeyCode::Tab) => self.indent(),
+            (_, KeyCode::BackTab) => self.outdent(),
+            (_, KeyCode::Char('e')) | (_, KeyCode::Char('i')) => self.start_edit(),
+            (_, KeyCode::Char(' ')) => self.cycle(),
+            (_, KeyCode::Char('q')) | (_, KeyCode::Esc) => return false,
+            _ => {}
+        }
+        true
+    }
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [body, bar] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let vessel = &mut *self.vessel;
+        let mut lines: Vec<String> = self.rows.iter().map(|(id, depth)| label(vessel, id, *depth)).collect();
+        let editing = match (&self.edit, self.rows.get(self.pos)) {
+            (Some(face), Some((_, depth))) => {
+                // Note: only the face while typing, so that the cursor sits at its end.
+                lines[self.pos] = format!("{}[ ] {}", "  ".repeat(*depth), face);
+                Some(Line::from(lines[self.pos].as_str()).width() as u16)
+            }
+            _ => None,
+        };
+        let list = List::new(lines.into_iter().map(ListItem::new))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        self.list.select(if self.rows.is_empty() { None } else { Some(self.pos) });
+        frame.render_stateful_widget(list, body, &mut self.list);
+        if let Some(width) = editing {
+            let y = body.y + (self.pos - self.list.offset()) as u16;
+            frame.set_cursor_position((body.x + width.min(body.width.saturating_sub(1)), y));
+        }
+        let text = if !self.message.is_empty() {
+            self.message.as_str()
+        } else if self.rows.is_empty() {
+            "empty; enter adds an entity"
+        } else {
+            HELP
+        };
+        frame.render_widget(Paragraph::new(text).style(Style::default().add_modifier(Modifier::DIM)), bar);
+    }
+}
+
+/// draws outliner on terminal after each of events, until it closes or events run out.
+pub fn session<B: Backend>(
+    terminal: &mut Terminal<B>, outliner: &mut Outliner, events: impl IntoIterator<Item = io::Result<Event>>,
+) -> io::Result<()> {
+    terminal.draw(|frame| outliner.draw(frame))?;
+    for event in events {
+        if let Event::Key(key) = event? {
+            if !outliner.handle(key) { break }
+        }
+        terminal.draw(|frame| outliner.draw(frame))?;
+    }
+    Ok(())
+}
+
+/// opens the outliner on vessel in the terminal.
+pub fn run(vessel: &mut Vessel, path: &Path) -> Result<(), CliError> {
+    let mut terminal = ratatui::init();
+    let mut outliner = Outliner::new(vessel, path);
+    let res = session(&mut terminal, &mut outliner, std::iter::repeat_with(event::read));
+    ratatui::restore();
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn key(code: KeyCode) -> io::Result<Event> {
+        Ok(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+    }
+
+    fn typed(text: &str) -> Vec<io::Result<Event>> {
+        text.chars().map(|c| key(KeyCode::Char(c))).collect()
+    }
+
+    fn screen(terminal: &Terminal<TestBackend>) -> Vec<String> {
+        let buffer = terminal.backend().buffer();
+        buffer.content().chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn outline() {
+        let path = std::env::temp_dir().join(format!("flow_cli-tui-{}.json", std::process::id()));
+        let mut vessel = Vessel::new();
+        let mut terminal = Terminal::new(TestBackend::new(40, 6)).unwrap();
+        let mut outliner = Outliner::new(&mut vessel, &path);
+        session(&mut terminal, &mut outliner, vec![]).unwrap();
+        assert_eq!(screen(&terminal)[5], "empty; enter adds an entity");
+
+        let mut events = vec![key(KeyCode::Enter)];
+        events.extend(typed("Plan"));
+        events.push(key(KeyCode::Enter));
+        events.extend(typed("Draft"));
+        events.push(key(KeyCode::Tab));
+        events.push(key(KeyCode::Enter));
+        events.extend(typed("Review"));
+        events.push(key(KeyCode::BackTab));
+        events.push(key(KeyCode::Enter));
+        events.extend(typed("Oops"));
+        events.extend((0..5).map(|_| key(KeyCode::Backspace)));
+        events.extend([key(KeyCode::Up), key(KeyCode::Char(' ')), key(KeyCode::Char(' '))]);
+        session(&mut terminal, &mut outliner, events).unwrap();
+        assert_eq!(screen(&terminal)[..4], [
+            "[ ] Plan",
+            "  [ ] Draft (Pending)",
+            "[ ] Review",
+            "",
+        ]);
+
+        // ctrl+↓ swaps Plan and Review; e edits in place
+        let ctrl = |code| Ok(Event::Key(KeyEvent::new(code, KeyModifiers::CONTROL)));
+        let mut events = vec![key(KeyCode::Up), ctrl(KeyCode::Down), key(KeyCode::Char('e'))];
+        events.extend(typed("s!"));
+        events.push(key(KeyCode::Esc));
+        session(&mut terminal, &mut outliner, events).unwrap();
+        assert_eq!(screen(&terminal)[..3], ["[ ] Review", "[ ] Plans!", "  [ ] Draft (Pending)"]);
+        assert!(!outliner.handle(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE)));
+
+        // every change is saved already
+        let mut saved = Vessel::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let top = saved.entity_top();
+        assert_eq!(top.len(), 2);
+        let plans = saved.entity_children(&top[1]);
+        assert_eq!(saved.entity_get(&top[1]).unwrap().face, "Plans!");
+        assert_eq!(saved.entity_get(&plans[0]).unwrap().process, ProcessStatus::Pending);
+    }
+}
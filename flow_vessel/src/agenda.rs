@@ -0,0 +1,208 @@
+//! agenda.rs contains a per-day projection of the vessel for calendar-like views:
+//! 1. scheduled and due times, recurrences expanded;
+//! 2. time worked, from TimeLog sessions split at midnight;
+//! 3. status changes from the history.
+//!
+//! days are taken in a given time zone; due items not Done by now are overdue.
+
+use std::{convert::TryFrom, time::{Duration, SystemTime}};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AgendaKind {
+    Scheduled,
+    Due,
+    /// a session, or the part of it falling on this day; time is where it starts.
+    Worked(Duration),
+    Transit { from: ProcessStatus, to: ProcessStatus },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgendaItem {
+    pub id: EntityId,
+    pub face: String,
+    pub kind: AgendaKind,
+    pub time: SystemTime,
+    pub overdue: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgendaDay {
+    pub date: NaiveDate,
+    /// ordered by time.
+    pub items: Vec<AgendaItem>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Agenda {
+    /// every day in the range, empty ones included.
+    pub days: Vec<AgendaDay>,
+    /// due before the range and still not Done.
+    pub overdue: Vec<AgendaItem>,
+}
+
+fn midnight<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> SystemTime {
+    let naive = date.and_hms_opt(0, 0, 0).expect("midnight");
+    tz.from_local_datetime(&naive).earliest()
+        .map(SystemTime::from)
+        // Note: a dst gap at midnight; take the utc reading.
+        .unwrap_or_else(|| SystemTime::from(Utc.from_utc_datetime(&naive)))
+}
+
+fn date_of<Tz: TimeZone>(time: SystemTime, tz: &Tz) -> NaiveDate {
+    DateTime::<Utc>::from(time).with_timezone(tz).date_naive()
+}
+
+/// t moved by to - from.
+fn offset(t: SystemTime, from: SystemTime, to: SystemTime) -> SystemTime {
+    match to.duration_since(from) {
+        Ok(d) => t + d,
+        Err(e) => t - e.duration(),
+    }
+}
+
+impl Entity {
+    fn agenda_item(&self, kind: AgendaKind, time: SystemTime, now: SystemTime) -> AgendaItem {
+        let overdue = kind == AgendaKind::Due && time < now && self.process != ProcessStatus::Done;
+        AgendaItem { id: self.id().clone(), face: self.face.clone(), kind, time, overdue }
+    }
+    /// agenda items of self within [start, end).
+    fn agenda_items<Tz: TimeZone>(&self, start: SystemTime, end: SystemTime, tz: &Tz, now: SystemTime) -> Vec<AgendaItem> {
+        let mut items = Vec::new();
+        let schedule = &self.schedule;
+        if let Some(anchor) = schedule.anchor() {
+            let fields = [(schedule.scheduled, AgendaKind::Scheduled), (schedule.due, AgendaKind::Due)];
+            for (field, kind) in fields.iter() {
+                if let Some(t) = field {
+                    // occurrences of the anchor that put this field within the span
+                    let hits = schedule.occurrences(offset(start, *t, anchor), offset(end, *t, anchor), tz);
+                    items.extend(hits.into_iter().map(|hit| self.agenda_item(kind.clone(), offset(hit, anchor, *t), now)));
+                }
+            }
+        }
+        for session in self.time.iter().flat_map(|log| log.sessions()) {
+            let mut from = session.start.max(start);
+            let until = session.end.unwrap_or(now).min(end);
+            while from < until {
+                let next = midnight(date_of(from, tz).succ_opt().unwrap_or(NaiveDate::MAX), tz);
+                let to = if next > from { next.min(until) } else { until };
+                let worked = to.duration_since(from).unwrap_or_default();
+                items.push(self.agenda_item(AgendaKind::Worked(worked), from, now));
+                from = to;
+            }
+        }
+        for tr in self.history.iter().filter(|tr| start <= tr.time && tr.time < end) {
+            items.push(self.agenda_item(AgendaKind::Transit { from: tr.from, to: tr.to }, tr.time, now));
+        }
+        items
+    }
+}
+
+impl Vessel {
+    /// the agenda for the days first..=last in tz, as seen at now.
+    pub fn agenda<Tz: TimeZone>(&mut self, first: NaiveDate, last: NaiveDate, tz: &Tz, now: SystemTime) -> Agenda {
+        self.scan(|vessel| {
+            let (start, end) = (midnight(first, tz), midnight(last.succ_opt().unwrap_or(last), tz));
+            let mut days: Vec<AgendaDay> = first.iter_days().take_while(|date| *date <= last)
+                .map(|date| AgendaDay { date, items: Vec::new() })
+                .collect();
+            let mut overdue = Vec::new();
+            for id in vessel.entity_preorder() {
+                let entity = &vessel.flow_arena.node_map[&id].entity;
+                for item in entity.agenda_items(start, end, tz, now) {
+                    let index = (date_of(item.time, tz) - first).num_days();
+                    if let Some(day) = usize::try_from(index).ok().and_then(|i| days.get_mut(i)) {
+                        day.items.push(item);
+                    }
+                }
+                if let Some(due) = entity.schedule.due.filter(|due| *due < start) {
+                    let item = entity.agenda_item(AgendaKind::Due, due, now);
+                    if item.overdue { overdue.push(item) }
+                }
+            }
+            for day in days.iter_mut() {
+                day.items.sort_by_key(|item| item.time);
+            }
+            overdue.sort_by_key(|item| item.time);
+            Agenda { days, overdue }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Weekday};
+    use crate::schedule::{Schedule, Recurrence};
+
+    #[test]
+    fn agenda() {
+        // UTC+8, so that local days differ from utc ones
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let at = |d: u32, h: u32| SystemTime::from(tz.with_ymd_and_hms(2021, 3, d, h, 0, 0).unwrap());
+        let date = |d: u32| NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        let now = at(3, 12);
+        let mut vessel = Vessel::new();
+
+        let report = vessel.entity_grow();
+        let entity = vessel.entity_get_mut(&report).unwrap();
+        entity.face = "Report".to_string();
+        entity.schedule = Schedule { scheduled: Some(at(1, 9)), due: Some(at(2, 17)), recurrence: None };
+        vessel.entity_start_timer(&report, at(1, 23)).unwrap();
+        vessel.entity_stop_timer(&report, at(2, 1)).unwrap();
+
+        let standup = vessel.entity_grow();
+        let entity = vessel.entity_get_mut(&standup).unwrap();
+        entity.face = "Standup".to_string();
+        entity.schedule = Schedule { scheduled: Some(at(1, 10)), due: None, recurrence: Some(Recurrence::Daily) };
+        vessel.entity_transit_at(&standup, ProcessStatus::Marching, at(2, 10)).unwrap();
+
+        let old = vessel.entity_grow();
+        vessel.entity_get_mut(&old).unwrap().schedule.due = Some(at(1, 0) - Duration::from_secs(3600));
+
+        let agenda = vessel.agenda(date(1), date(3), &tz, now);
+        assert_eq!(agenda.days.iter().map(|day| day.date).collect::<Vec<_>>(), vec![date(1), date(2), date(3)]);
+        let kinds = |d: usize| agenda.days[d].items.iter().map(|item| (&item.id, item.kind.clone())).collect::<Vec<_>>();
+        assert_eq!(kinds(0), vec![
+            (&report, AgendaKind::Scheduled),
+            (&standup, AgendaKind::Scheduled),
+            (&report, AgendaKind::Worked(Duration::from_secs(3600))),
+        ]);
+        assert_eq!(kinds(1), vec![
+            (&report, AgendaKind::Worked(Duration::from_secs(3600))),
+            (&standup, AgendaKind::Scheduled),
+            (&standup, AgendaKind::Transit { from: ProcessStatus::New, to: ProcessStatus::Marching }),
+            (&report, AgendaKind::Due),
+        ]);
+        assert!(agenda.days[1].items[3].overdue);
+        assert_eq!(kinds(2), vec![(&standup, AgendaKind::Scheduled)]);
+        assert_eq!(agenda.overdue.iter().map(|item| &item.id).collect::<Vec<_>>(), vec![&old]);
+        // paged out entities show up, and stay paged out
+        vessel.entity_move(&standup, Some(&report)).unwrap();
+        vessel.page_out(&report).unwrap();
+        assert_eq!(vessel.agenda(date(1), date(3), &tz, now).days[2].items.len(), 1);
+        assert!(vessel.flow_arena.is_dormant(&report));
+    }
+
+    #[test]
+    fn weekly_in_tz() {
+        // 00:30 on Mondays at UTC+8 is still Sunday in utc
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let date = |d: u32| NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        let at = |d: u32| SystemTime::from(tz.with_ymd_and_hms(2021, 3, d, 0, 30, 0).unwrap());
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        vessel.entity_get_mut(&id).unwrap().schedule = Schedule {
+            scheduled: Some(at(1)), due: None, recurrence: Some(Recurrence::Weekly(vec![Weekday::Mon])),
+        };
+        let agenda = vessel.agenda(date(1), date(21), &tz, at(1));
+        let found: Vec<(NaiveDate, SystemTime)> = agenda.days.iter()
+            .flat_map(|day| day.items.iter().map(move |item| (day.date, item.time)))
+            .collect();
+        assert_eq!(found, vec![(date(1), at(1)), (date(8), at(8)), (date(15), at(15))]);
+    }
+}
@@ -0,0 +1,347 @@
+//! archive.rs contains the archive of a Vessel, for finished work that only slows the tree down:
+//! 1. archive_done moves each topmost subtree whose entities are all Done, optionally for a while,
+//!    into a separate arena, with the path it had;
+//! 2. the archive is searched by archive_search; the tree, its search and the exports don't see it;
+//! 3. entity_unarchive puts a subtree back where it was, or at the end of root if its parent is gone.
+
+use std::{collections::{HashMap, HashSet}, fmt, time::{Duration, SystemTime}};
+use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError, Flow, FlowArena, Node};
+
+use super::{Vessel, ResolveError, resolve_among};
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::journal::{Mutation, Place};
+use super::search::{Query, SearchHit, SearchIndex};
+use super::trash::Subtree;
+
+/// an archived subtree; its entities live in the archive arena.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Archived {
+    id: EntityId,
+    /// the ancestors it had from the top down, with their faces at the time.
+    path: Vec<(EntityId, String)>,
+    place: Place,
+    archived: SystemTime,
+}
+
+impl Archived {
+    pub fn id(&self) -> &EntityId {
+        &self.id
+    }
+    pub fn path(&self) -> &[(EntityId, String)] {
+        &self.path
+    }
+    pub fn archived(&self) -> SystemTime {
+        self.archived
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Archive {
+    /// each archived subtree hangs below the root of the arena, oldest first.
+    arena: FlowArena<EntityId, Entity>,
+    entries: Vec<Archived>,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Archive { arena: FlowArena::new(), entries: Vec::new() }
+    }
+}
+
+impl Archive {
+    /// the subtree of the archived id in preorder, as subtree_take makes it.
+    fn subtree(&self, id: &EntityId) -> Subtree {
+        let arena = &self.arena;
+        let mut stack = vec![(id.clone(), None)];
+        let mut nodes: Subtree = Vec::new();
+        while let Some((x, parent)) = stack.pop() {
+            let node = match arena.node_map.get(&x) { Some(node) => node, None => continue };
+            let k = nodes.len();
+            stack.extend(node.children.iter().rev().map(|child| (child.clone(), Some(k))));
+            nodes.push((node.entity.clone(), parent));
+        }
+        nodes
+    }
+    /// puts entry with its subtree as the nth, or last, replacing the one with its id.
+    pub(crate) fn put(&mut self, entry: Archived, nodes: Subtree, nth: usize) {
+        self.remove(&entry.id);
+        let nth = nth.min(self.entries.len());
+        let ids: Vec<EntityId> = nodes.iter().map(|(entity, _)| entity.id().clone()).collect();
+        let root = self.arena.root.clone();
+        for (entity, parent) in nodes {
+            let x = entity.id().clone();
+            self.arena.grow(Node::from_id(x.clone(), entity)).ok();
+            match parent {
+                Some(parent) => { self.arena.devote_push(&x, &ids[parent]).ok(); }
+                None => { self.arena.relocate(&x, &root, nth).ok(); }
+            }
+        }
+        self.entries.insert(nth, entry);
+    }
+    /// removes the entry of id with its subtree.
+    pub(crate) fn remove(&mut self, id: &EntityId) {
+        let idx = match self.entries.iter().position(|entry| entry.id == *id) { Some(idx) => idx, None => return };
+        self.entries.remove(idx);
+        for (entity, _) in self.subtree(id).iter().rev() {
+            self.arena.decay(entity.id()).ok();
+        }
+    }
+    /// puts for the ids with an entry and removals for the others, as journal::mutations does.
+    pub(crate) fn mutations(&self, ids: impl IntoIterator<Item = EntityId>) -> Vec<Mutation> {
+        let mut batch = Vec::new();
+        let mut put = Vec::new();
+        for id in ids {
+            match self.entries.iter().position(|entry| entry.id == id) {
+                Some(nth) => put.push(nth),
+                None => batch.push(Mutation::ArchiveRemove { id }),
+            }
+        }
+        put.sort();
+        batch.extend(put.into_iter().map(|nth| {
+            let entry = self.entries[nth].clone();
+            let nodes = self.subtree(&entry.id);
+            Mutation::ArchivePut { entry, nodes, nth }
+        }));
+        batch
+    }
+}
+
+impl fmt::Debug for Archive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archive")
+         .field("entities", &self.arena.node_map.len().saturating_sub(1))
+         .field("entries", &self.entries)
+         .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// no archived subtree starts at the id.
+    Missing(EntityId),
+    /// an entity of the subtree exists again in the vessel.
+    Occupied(EntityId),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Missing(id) => write!(f, "{} is not archived", id),
+            ArchiveError::Occupied(id) => write!(f, "{} exists in the vessel again", id),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl Vessel {
+    /// moves id and its subtree into the archive at time; false if id doesn't exist or names a tree.
+    pub fn entity_archive(&mut self, id: &EntityId, time: SystemTime) -> bool {
+        if self.flow_arena.forest.iter().any(|tree| tree.id == *id) { return false }
+        self.flow_arena.materialize(id).ok();
+        let path = self.entity_path(id).into_iter().map(|x| {
+            let face = self.flow_arena.node(&x).map(|node| node.entity.face.clone()).unwrap_or_default();
+            (x, face)
+        }).collect();
+        let (nodes, place) = match self.subtree_take(id) { Some(taken) => taken, None => return false };
+        self.archive.put(Archived { id: id.clone(), path, place, archived: time }, nodes, usize::MAX);
+        self.changes.archived.insert(id.clone());
+        true
+    }
+    /// archives each topmost subtree whose entities are all Done, and done for older_than at now if given;
+    /// with older_than, entities without a recorded completion stay. returns the archived ids in tree order.
+    pub fn archive_done(&mut self, older_than: Option<Duration>, now: SystemTime) -> Vec<EntityId> {
+        let picked = self.scan(|vessel| {
+            let cutoff = older_than.map(|age| now.checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH));
+            let finished = |entity: &Entity| entity.process == ProcessStatus::Done
+                && cutoff.is_none_or(|cutoff| entity.completed_at().is_some_and(|time| time <= cutoff));
+            let order = vessel.entity_preorder();
+            let arena = &vessel.flow_arena;
+            let mut done: HashMap<&EntityId, bool> = HashMap::new();
+            for id in order.iter().rev() {
+                let node = &arena.node_map[id];
+                let all = finished(&node.entity) && node.children.iter().all(|child| done.get(child) == Some(&true));
+                done.insert(id, all);
+            }
+            // Note: in preorder, a parent is covered before its children come up.
+            let mut covered = HashSet::new();
+            let mut picked = Vec::new();
+            for id in order.iter() {
+                let parent = arena.node_map[id].parent.as_ref();
+                if parent.is_some_and(|parent| covered.contains(parent)) {
+                    covered.insert(id);
+                } else if done[id] && parent.is_some() {
+                    covered.insert(id);
+                    picked.push(id.clone());
+                }
+            }
+            picked
+        });
+        for id in picked.iter() {
+            self.entity_archive(id, now);
+        }
+        picked
+    }
+    /// the archived subtrees, oldest first.
+    pub fn archive_list(&self) -> &[Archived] {
+        &self.archive.entries
+    }
+    /// an archived subtree by its id, or a unique hex prefix of it; see resolve_prefix.
+    pub fn archive_resolve(&self, text: &str) -> Result<EntityId, ResolveError> {
+        resolve_among(self.archive.entries.iter().map(|entry| &entry.id).collect(), text)
+    }
+    /// an entity anywhere in the archive.
+    pub fn archive_get(&self, id: &EntityId) -> Option<&Entity> {
+        let arena = &self.archive.arena;
+        arena.node_map.get(id).filter(|_| *id != arena.root).map(|node| &node.entity)
+    }
+    pub fn archive_children(&self, id: &EntityId) -> Vec<EntityId> {
+        self.archive.arena.node_map.get(id).map(|node| node.children.clone()).unwrap_or_default()
+    }
+    /// the path of an archived entity: the one its subtree had in the tree, then its ancestors in the archive.
+    fn archive_path(&self, id: &EntityId) -> Vec<EntityId> {
+        let arena = &self.archive.arena;
+        let mut path = Vec::new();
+        let mut cur = arena.node_map.get(id).and_then(|node| node.parent.clone());
+        while let Some(parent) = cur {
+            if parent == arena.root { break }
+            cur = arena.node_map.get(&parent).and_then(|node| node.parent.clone());
+            path.push(parent);
+        }
+        let top = path.last().unwrap_or(id);
+        let above = self.archive.entries.iter().find(|entry| entry.id == *top).map(|entry| entry.path.as_slice()).unwrap_or_default();
+        path.extend(above.iter().rev().map(|(x, _)| x.clone()));
+        path.reverse();
+        path
+    }
+    /// as search, over the archive.
+    pub fn archive_search(&self, query: &str) -> Vec<SearchHit> {
+        let query = Query::parse(query);
+        if query.is_empty() { return Vec::new() }
+        let arena = &self.archive.arena;
+        let index = SearchIndex::build(arena.entities().filter(|entity| entity.id() != &arena.root));
+        index.query(&query).into_iter().map(|(id, score, snippets)| {
+            let path = self.archive_path(&id);
+            SearchHit { id, score, snippets, path }
+        }).collect()
+    }
+    /// puts the archived subtree of id back: at its former place if the parent still exists, else at the end of root.
+    pub fn entity_unarchive(&mut self, id: &EntityId) -> Result<(), ArchiveError> {
+        let place = self.archive.entries.iter().find(|entry| entry.id == *id)
+            .map(|entry| entry.place.clone())
+            .ok_or_else(|| ArchiveError::Missing(id.clone()))?;
+        let nodes = self.archive.subtree(id);
+        if let Some(x) = self.subtree_clash(&nodes) {
+            return Err(ArchiveError::Occupied(x))
+        }
+        self.archive.remove(id);
+        self.changes.archived.insert(id.clone());
+        self.subtree_put(nodes, &place);
+        Ok(())
+    }
+}
+
+impl Compact for Archived {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.id.encode(buf);
+        (self.path.len() as u64).encode(buf);
+        for (id, face) in self.path.iter() {
+            id.encode(buf);
+            face.encode(buf);
+        }
+        self.place.encode(buf);
+        self.archived.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let id = EntityId::decode(src)?;
+        let len = u64::decode(src)?;
+        let mut path = Vec::new();
+        for _ in 0..len {
+            let x = EntityId::decode(src)?;
+            path.push((x, String::decode(src)?));
+        }
+        Ok(Archived { id, path, place: Place::decode(src)?, archived: SystemTime::decode(src)? })
+    }
+}
+
+impl Compact for Archive {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.arena.encode(buf);
+        self.entries.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(Archive { arena: Compact::decode(src)?, entries: Vec::decode(src)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, DAY};
+
+    fn add(vessel: &mut Vessel, face: &str, under: Option<&EntityId>, done: Option<u64>) -> EntityId {
+        let id = vessel.entity_grow();
+        vessel.entity_get_mut(&id).unwrap().face = face.to_string();
+        vessel.entity_move(&id, under).unwrap();
+        if let Some(day) = done {
+            vessel.entity_transit_at(&id, ProcessStatus::Done, at(day * DAY)).unwrap();
+        }
+        id
+    }
+
+    #[test]
+    fn archive() {
+        let mut vessel = Vessel::new();
+        let project = add(&mut vessel, "Project", None, None);
+        let launch = add(&mut vessel, "Launch", Some(&project), Some(1));
+        let slides = add(&mut vessel, "Slides", Some(&launch), Some(1));
+        let fresh = add(&mut vessel, "Fresh", Some(&project), Some(9));
+        let open = add(&mut vessel, "Open", Some(&project), None);
+        let draft = add(&mut vessel, "Draft", Some(&open), Some(1));
+
+        // only the topmost finished subtrees that are old enough; Project has an open task
+        assert_eq!(vessel.archive_done(Some(Duration::from_secs(5 * DAY)), at(10 * DAY)), vec![launch.clone(), draft]);
+        assert_eq!(vessel.entity_children(&project), vec![fresh.clone(), open.clone()]);
+        assert!(vessel.entity_get(&slides).is_none());
+        assert!(vessel.search("slides").is_empty());
+        let entry = &vessel.archive_list()[0];
+        assert_eq!(entry.path(), [(project.clone(), "Project".to_string())]);
+        assert_eq!(vessel.archive_resolve(&format!("{:x}", launch.unique())), Ok(launch.clone()));
+        assert!(vessel.archive_resolve(&format!("{:x}", slides.unique())).is_err());
+        assert_eq!(vessel.archive_get(&slides).unwrap().face, "Slides");
+
+        let hits = vessel.archive_search("slides");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, vec![project.clone(), launch.clone()]);
+
+        vessel.entity_unarchive(&launch).unwrap();
+        assert_eq!(vessel.entity_children(&project), vec![launch.clone(), fresh.clone(), open.clone()]);
+        assert_eq!(vessel.entity_children(&launch), vec![slides.clone()]);
+        assert_eq!(vessel.entity_unarchive(&launch), Err(ArchiveError::Missing(launch.clone())));
+        assert!(vessel.archive_search("slides").is_empty());
+
+        // without an age, Fresh goes, too; saved with the vessel
+        assert_eq!(vessel.archive_done(None, at(10 * DAY)), vec![launch.clone(), fresh.clone()]);
+        let mut saved = Vessel::from_json(&vessel.to_json().unwrap()).unwrap();
+        assert_eq!(saved.archive_list(), vessel.archive_list());
+        assert_eq!(saved.archive_search("slides").len(), 1);
+        let mut compact = Vessel::from_compact(&vessel.to_compact()).unwrap();
+        compact.entity_unarchive(&fresh).unwrap();
+        assert_eq!(compact.entity_children(&project), vec![fresh.clone(), open.clone()]);
+        saved.entity_decay(&project);
+        saved.entity_unarchive(&launch).unwrap();
+        assert_eq!(saved.entity_top().last(), Some(&launch));
+
+        // paged out subtrees are looked at; only the ones archived from are paged in
+        let shelf = add(&mut vessel, "Shelf", None, None);
+        add(&mut vessel, "Idle", Some(&shelf), None);
+        let boxed = add(&mut vessel, "Box", None, None);
+        let old = add(&mut vessel, "Old", Some(&boxed), Some(1));
+        vessel.page_out(&shelf).unwrap();
+        vessel.page_out(&boxed).unwrap();
+        assert_eq!(vessel.archive_done(None, at(10 * DAY)), vec![old]);
+        assert!(vessel.flow_arena.is_dormant(&shelf));
+    }
+}
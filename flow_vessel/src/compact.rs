@@ -0,0 +1,109 @@
+use flow_arena::{Compact, CompactError, compact::decode_bytes};
+
+use super::Vessel;
+use super::identity::EntityIdFactory;
+
+/// magic header of an encoded Vessel.
+pub const VESSEL_MAGIC: &[u8; 4] = b"FLVS";
+/// current vessel layout version; bumped whenever the layout changes.
+pub const VESSEL_VERSION: u8 = 5;
+
+/// layout: "FLVS" | version: u8 | FlowArena | EntityIdFactory | TransitionTable (since 2) | Trash (since 4) | Archive (since 5);
+/// before 3, EntityIdFactory is its counter only.
+impl Compact for Vessel {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(VESSEL_MAGIC);
+        buf.push(VESSEL_VERSION);
+        self.flow_arena.encode(buf);
+        self.id_factory.encode(buf);
+        self.transitions.encode(buf);
+        self.trash.encode(buf);
+        self.archive.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        if decode_bytes(src, VESSEL_MAGIC.len())? != VESSEL_MAGIC {
+            return Err(CompactError::BadMagic);
+        }
+        let version = u8::decode(src)?;
+        if version == 0 || version > VESSEL_VERSION {
+            return Err(CompactError::UnknownVersion(version));
+        }
+        Ok(Vessel {
+            flow_arena: Compact::decode(src)?,
+            id_factory: if version >= 3 { Compact::decode(src)? } else { EntityIdFactory::decode_counter(src)? },
+            transitions: if version >= 2 { Compact::decode(src)? } else { Default::default() },
+            trash: if version >= 4 { Compact::decode(src)? } else { Default::default() },
+            archive: if version >= 5 { Compact::decode(src)? } else { Default::default() },
+            search: Default::default(),
+            links: Default::default(),
+            changes: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::ProcessStatus;
+    use flow_arena::Flow;
+
+    fn make_vessel(size: usize) -> Vessel {
+        let mut vessel = Vessel::new();
+        let mut ids = Vec::new();
+        for i in 0..size {
+            let id = vessel.entity_grow();
+            if let Some(entity) = vessel.entity_get_mut(&id) {
+                entity.face = format!("Task number {}", i);
+                entity.bubble = format!("Some notes on task {}, written in the bubble.", i);
+                entity.tags.push(format!("tag{}", i % 7));
+                if i % 3 == 0 { entity.process = ProcessStatus::Done }
+            }
+            // a few levels of nesting
+            if i > 0 {
+                vessel.flow_arena.devote_push(&id, &ids[(i - 1) / 4]).ok();
+            }
+            ids.push(id);
+        }
+        vessel
+    }
+
+    #[test]
+    fn round_trip() {
+        let vessel = make_vessel(40);
+        let bytes = vessel.to_compact();
+        let _vessel = Vessel::from_compact(&bytes).unwrap();
+        assert_eq!(vessel.flow_arena, _vessel.flow_arena);
+        assert_eq!(bytes, _vessel.to_compact());
+    }
+
+    #[test]
+    fn size_against_json() {
+        let vessel = make_vessel(200);
+        let json = serde_json::to_vec(&vessel.flow_arena).unwrap();
+        let compact = vessel.to_compact();
+        println!("json: {} bytes, compact: {} bytes ({:.1}%)",
+            json.len(), compact.len(), 100.0 * compact.len() as f64 / json.len() as f64);
+        assert!(compact.len() * 2 < json.len());
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut bytes = Vessel::new().to_compact();
+        bytes[4] = VESSEL_VERSION + 1;
+        assert_eq!(Vessel::from_compact(&bytes).err(), Some(CompactError::UnknownVersion(VESSEL_VERSION + 1)));
+    }
+
+    #[test]
+    fn version_1() {
+        let vessel = make_vessel(4);
+        // version 1: the factory is its counter, and no transition table follows
+        let mut bytes = VESSEL_MAGIC.to_vec();
+        bytes.push(1);
+        vessel.flow_arena.encode(&mut bytes);
+        7u64.encode(&mut bytes);
+        let mut _vessel = Vessel::from_compact(&bytes).unwrap();
+        assert_eq!(vessel.flow_arena, _vessel.flow_arena);
+        assert_eq!(_vessel.transitions, crate::TransitionTable::default());
+        assert_eq!(_vessel.id_factory.incr_id().unique(), 8);
+    }
+}
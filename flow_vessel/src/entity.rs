@@ -1,13 +1,17 @@
+use std::time::SystemTime;
 use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError, compact::{encode_varint, decode_varint}};
 
 use super::identity::*;
 use super::tag::*;
 use super::time::TimeLog;
+use super::process::Transition;
+use super::schedule::Schedule;
 
 
 // Entity Area
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Entity {
     id: EntityId,
     pub time: Option<TimeLog>,
@@ -15,11 +19,20 @@ pub struct Entity {
     pub bubble: Bubble,
     pub process: ProcessStatus,
     pub tags: TagSet,
+    /// status moves made through Vessel::entity_transit, oldest first.
+    #[serde(default)]
+    pub history: Vec<Transition>,
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// when the entity was created, if that's not the time of its id, e.g. a date read off an import.
+    #[serde(default)]
+    pub created: Option<SystemTime>,
 }
 
 impl Entity {
-    pub fn new_time(id_factory: &EntityIdFactory) -> Self {
-        Self::new_id(&id_factory.time_id())
+    /// an id as the factory's mode says.
+    pub fn new_time(id_factory: &mut EntityIdFactory) -> Self {
+        Self::new_id(&id_factory.next_id())
     }
     pub fn new_incr(id_factory: &mut EntityIdFactory) -> Self {
         Self::new_id(&id_factory.incr_id())
@@ -31,12 +44,19 @@ impl Entity {
             face: Face::new(),
             bubble: Bubble::new(),
             process: ProcessStatus::New,
-            tags: TagSet::new()
+            tags: TagSet::new(),
+            history: Vec::new(),
+            schedule: Schedule::default(),
+            created: None,
         }
     }
     pub fn id(&self) -> &EntityId {
         &self.id
     }
+    /// created if recorded, or else the time of the id.
+    pub fn created(&self) -> SystemTime {
+        self.created.unwrap_or(*self.id.time())
+    }
     // pub fn update_entity(&mut self, field: EntityField) {
     //     use EntityField::*;
     //     match field {
@@ -51,6 +71,49 @@ impl Entity {
     // }
 }
 
+/// number of fields after the id; older data with fewer fields decodes the rest to default.
+/// field 0 held the single-interval TimeLog and is now always empty; sessions live in field 6.
+const ENTITY_FIELDS: u64 = 9;
+
+impl Compact for Entity {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.id.encode(buf);
+        encode_varint(ENTITY_FIELDS, buf);
+        0u8.encode(buf);
+        self.face.encode(buf);
+        self.bubble.encode(buf);
+        self.process.encode(buf);
+        self.tags.encode(buf);
+        self.history.encode(buf);
+        self.time.encode(buf);
+        self.schedule.encode(buf);
+        self.created.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let mut entity = Entity::new_id(&EntityId::decode(src)?);
+        let fields = decode_varint(src)?;
+        for field in 0..fields {
+            match field {
+                0 => entity.time = match u8::decode(src)? {
+                    0 => None,
+                    1 => Some(TimeLog::decode_single(src)?),
+                    t => return Err(CompactError::InvalidTag(t)),
+                },
+                1 => entity.face = Face::decode(src)?,
+                2 => entity.bubble = Bubble::decode(src)?,
+                3 => entity.process = ProcessStatus::decode(src)?,
+                4 => entity.tags = TagSet::decode(src)?,
+                5 => entity.history = Vec::decode(src)?,
+                6 => entity.time = Option::decode(src)?,
+                7 => entity.schedule = Schedule::decode(src)?,
+                8 => entity.created = Option::decode(src)?,
+                _ => return Err(CompactError::InvalidIndex(field)),
+            }
+        }
+        Ok(entity)
+    }
+}
+
 impl Default for Entity {
     fn default() -> Self { 
         Self::new_id(&EntityId::default())
@@ -61,7 +124,7 @@ impl Default for Entity {
 pub type Face = String;
 pub type Bubble = String;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ProcessStatus {
     Done,
     Marching,
@@ -101,11 +164,37 @@ impl ProcessStatus {
             Done,
         }
     }
+    /// position in vec_all.
+    pub fn order(&self) -> usize {
+        match self {
+            New => 0,
+            Planning => 1,
+            Pending => 2,
+            Marching => 3,
+            Done => 4,
+        }
+    }
     pub fn type_src(&self) -> String {
         format!("static/icons/Process/{}.svg", Self::type_str(self))
     }
 }
 
+impl Compact for ProcessStatus {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.order() as u8).encode(buf)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        match u8::decode(src)? {
+            0 => Ok(New),
+            1 => Ok(Planning),
+            2 => Ok(Pending),
+            3 => Ok(Marching),
+            4 => Ok(Done),
+            t => Err(CompactError::InvalidTag(t)),
+        }
+    }
+}
+
 // #[derive(Debug, Clone)]
 // pub enum EntityField {
 //     TimeStamp(TimeStamp),
@@ -0,0 +1,240 @@
+//! ics.rs contains the iCalendar export, to see tasks in a calendar app:
+//! 1. a VTODO for each entity with a scheduled or due time, recurrence as RRULE;
+//! 2. a VEVENT for each finished TimeLog session.
+//!
+//! UIDs are made from the EntityId (and the session start), so re-exports update instead of duplicating.
+//! times are written in utc, but a recurring VTODO has floating local times, since its rule repeats in Local;
+//! text is escaped and lines folded at 75 octets as RFC 5545 asks.
+
+use std::time::SystemTime;
+use chrono::{DateTime, Local, Utc, Weekday};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::schedule::Recurrence;
+
+const LINE: usize = 75;
+
+/// escapes a TEXT value.
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            ';' => res.push_str("\\;"),
+            ',' => res.push_str("\\,"),
+            '\n' => res.push_str("\\n"),
+            '\r' => {}
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// appends line with CRLF, folded into chunks of at most 75 octets without splitting a character.
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        // Note: continuation lines start with a space, which counts.
+        if width + c.len_utf8() > LINE {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// floating: the same wall-clock time wherever the calendar is.
+fn format_local(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn uid(id: &EntityId, suffix: &str) -> String {
+    format!("{}{}@flow.er", id.to_string().replace('@', "-"), suffix)
+}
+
+fn weekday(day: &Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// the RRULE value; None for AfterCompletion, which depends on when it's done.
+fn rrule(recurrence: &Recurrence) -> Option<String> {
+    match recurrence {
+        Recurrence::Daily => Some("FREQ=DAILY".to_string()),
+        Recurrence::Weekly(days) if days.is_empty() => Some("FREQ=WEEKLY".to_string()),
+        Recurrence::Weekly(days) => {
+            Some(format!("FREQ=WEEKLY;BYDAY={}", days.iter().map(weekday).collect::<Vec<_>>().join(",")))
+        }
+        // Note: short months use their last day, i.e. the last of 28..=day that exists.
+        Recurrence::Monthly(day) if *day > 28 => {
+            let days: Vec<String> = (28..=(*day).min(31)).map(|d| d.to_string()).collect();
+            Some(format!("FREQ=MONTHLY;BYMONTHDAY={};BYSETPOS=-1", days.join(",")))
+        }
+        Recurrence::Monthly(day) => Some(format!("FREQ=MONTHLY;BYMONTHDAY={}", day.max(&1))),
+        Recurrence::AfterCompletion(_) => None,
+    }
+}
+
+fn status(process: ProcessStatus) -> &'static str {
+    match process {
+        ProcessStatus::New | ProcessStatus::Planning | ProcessStatus::Pending => "NEEDS-ACTION",
+        ProcessStatus::Marching => "IN-PROCESS",
+        ProcessStatus::Done => "COMPLETED",
+    }
+}
+
+impl Entity {
+    /// the components of self; stamp is the DTSTAMP of each.
+    fn ics_write(&self, stamp: &str, out: &mut String) {
+        let common = |out: &mut String| {
+            push_line(out, &format!("DTSTAMP:{}", stamp));
+            push_line(out, &format!("SUMMARY:{}", escape(&self.face)));
+            if !self.bubble.is_empty() {
+                push_line(out, &format!("DESCRIPTION:{}", escape(&self.bubble)));
+            }
+            let tags: Vec<String> = self.tags.into_iter().map(|tag| escape(&tag)).collect();
+            if !tags.is_empty() {
+                push_line(out, &format!("CATEGORIES:{}", tags.join(",")));
+            }
+        };
+        let schedule = &self.schedule;
+        if !schedule.is_empty() {
+            push_line(out, "BEGIN:VTODO");
+            push_line(out, &format!("UID:{}", uid(self.id(), "")));
+            common(out);
+            let rule = schedule.recurrence.as_ref().and_then(rrule);
+            // Note: an RRULE counts from DTSTART, so a recurring task without scheduled starts at its due.
+            let start = if rule.is_some() { schedule.anchor() } else { schedule.scheduled };
+            let format = if rule.is_some() { format_local } else { format_time };
+            if let Some(start) = start {
+                push_line(out, &format!("DTSTART:{}", format(start)));
+            }
+            // Note: DUE must come after DTSTART; a due that became the start is left at that.
+            if let Some(due) = schedule.due.filter(|due| start.is_none_or(|start| *due > start)) {
+                push_line(out, &format!("DUE:{}", format(due)));
+            }
+            if let Some(rule) = rule {
+                push_line(out, &format!("RRULE:{}", rule));
+            }
+            push_line(out, &format!("STATUS:{}", status(self.process)));
+            if let Some(completed) = self.completed_at() {
+                push_line(out, &format!("COMPLETED:{}", format_time(completed)));
+            }
+            push_line(out, "END:VTODO");
+        }
+        for session in self.time.iter().flat_map(|log| log.sessions()) {
+            // Note: a running session has no end yet.
+            let end = match session.end {
+                Some(end) => end,
+                None => continue,
+            };
+            let start = session.start.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            push_line(out, "BEGIN:VEVENT");
+            push_line(out, &format!("UID:{}", uid(self.id(), &format!("-{}.{:09}", start.as_secs(), start.subsec_nanos()))));
+            common(out);
+            push_line(out, &format!("DTSTART:{}", format_time(session.start)));
+            push_line(out, &format!("DTEND:{}", format_time(end)));
+            push_line(out, "END:VEVENT");
+        }
+    }
+}
+
+impl Vessel {
+    /// the scheduled entities and work sessions of id and its subtree as an iCalendar file;
+    /// the whole vessel if id is None. now is the DTSTAMP.
+    pub fn export_ics(&mut self, id: Option<&EntityId>, now: SystemTime) -> String {
+        let top = match id {
+            Some(id) => vec![id.clone()],
+            None => self.entity_top(),
+        };
+        let stamp = format_time(now);
+        let mut out = String::new();
+        push_line(&mut out, "BEGIN:VCALENDAR");
+        push_line(&mut out, "VERSION:2.0");
+        push_line(&mut out, "PRODID:-//flow.er//flow_vessel//EN");
+        push_line(&mut out, "CALSCALE:GREGORIAN");
+        for top in top.iter() {
+            for id in self.entity_subtree(top) {
+                if let Some(entity) = self.flow_arena.node_map.get(&id) {
+                    entity.entity.ics_write(&stamp, &mut out);
+                }
+            }
+        }
+        push_line(&mut out, "END:VCALENDAR");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Schedule;
+    use crate::testing::at;
+
+    #[test]
+    fn text() {
+        assert_eq!(escape("a, b; c\\d\r\ne"), "a\\, b\\; c\\\\d\\ne");
+        let mut out = String::new();
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        push_line(&mut out, &line);
+        let lines: Vec<&str> = out.split("\r\n").collect();
+        assert!(lines.iter().all(|line| line.len() <= LINE));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.concat().replace(" é", "é"), line);
+        assert_eq!(rrule(&Recurrence::Monthly(30)).unwrap(), "FREQ=MONTHLY;BYMONTHDAY=28,29,30;BYSETPOS=-1");
+        assert_eq!(rrule(&Recurrence::Weekly(vec![Weekday::Mon, Weekday::Fri])).unwrap(), "FREQ=WEEKLY;BYDAY=MO,FR");
+    }
+
+    #[test]
+    fn export() {
+        let mut vessel = Vessel::new();
+        let report = vessel.entity_grow();
+        let entity = vessel.entity_get_mut(&report).unwrap();
+        entity.face = "Report, final".to_string();
+        entity.schedule = Schedule { scheduled: None, due: Some(at(86400)), recurrence: Some(Recurrence::Daily) };
+        vessel.entity_start_timer(&report, at(3600)).unwrap();
+        vessel.entity_stop_timer(&report, at(7200)).unwrap();
+        vessel.entity_start_timer(&report, at(9000)).unwrap();
+        vessel.entity_grow();
+
+        let ics = vessel.export_ics(None, at(0));
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n") && ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VTODO").count(), 1);
+        // the running session is left out
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("SUMMARY:Report\\, final\r\n"));
+        // a recurring due alone is the start, in floating local time
+        let local = format_local(at(86400));
+        assert!(!local.ends_with('Z'));
+        assert!(ics.contains(&format!("DTSTART:{}\r\nRRULE:FREQ=DAILY\r\nSTATUS:NEEDS-ACTION\r\n", local)));
+        assert!(ics.contains("DTSTART:19700101T010000Z\r\nDTEND:19700101T020000Z\r\n"));
+        assert!(ics.contains(&format!("UID:{}@flow.er\r\n", report.to_string().replace('@', "-"))));
+        // stable across exports
+        assert_eq!(ics, vessel.export_ics(None, at(0)));
+        // with a start, the due stays
+        vessel.entity_get_mut(&report).unwrap().schedule.scheduled = Some(at(3600));
+        let ics = vessel.export_ics(None, at(0));
+        assert!(ics.contains(&format!("DTSTART:{}\r\nDUE:{}\r\nRRULE:", format_local(at(3600)), local)));
+        // without a rule, a due time alone has no DTSTART, and times are in utc
+        let schedule = &mut vessel.entity_get_mut(&report).unwrap().schedule;
+        schedule.scheduled = None;
+        schedule.recurrence = None;
+        let ics = vessel.export_ics(None, at(0));
+        assert!(ics.contains("SUMMARY:Report\\, final\r\nDUE:19700102T000000Z\r\nSTATUS:"));
+    }
+}
@@ -1,6 +1,7 @@
-use std::{fmt::Debug, hash::Hash};
+use std::{collections::BTreeMap, fmt::{self, Debug, Display}, hash::Hash, str::FromStr, time::Duration};
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError, compact::decode_bytes};
 
 use super::time::TimeRep;
 
@@ -9,7 +10,8 @@ const LEN: usize = 5;
 pub trait Identity: Default + Debug + Clone + Hash + PartialEq + Eq {
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+/// ordered by time, then unique.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct EntityId {
     time: SystemTime,
     unique: u64
@@ -47,12 +49,165 @@ impl Debug for EntityId {
 
 impl Identity for EntityId {}
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+impl EntityId {
+    /// for ids carried over from elsewhere, e.g. legacy data.
+    pub(crate) fn from_parts(time: SystemTime, unique: u64) -> Self {
+        EntityId { time, unique }
+    }
+    /// when the id was made, i.e. when the entity was created.
+    pub fn time(&self) -> &SystemTime {
+        &self.time
+    }
+    pub fn unique(&self) -> u64 {
+        self.unique
+    }
+}
+
+/// the full, stable text form: `<unique as 16 hex>@<secs>.<nanos as 9 digits>`,
+/// e.g. `00f3a9c2b7d11e04@1614000000.000000000`.
+impl Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(f, "{:016x}@{}.{:09}", self.unique, since.as_secs(), since.subsec_nanos())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseIdError {
+    /// no `@` between unique and time.
+    MissingTime,
+    BadUnique,
+    BadTime,
+}
+
+impl Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseIdError::MissingTime => write!(f, "entity id lacks the `@time` part"),
+            ParseIdError::BadUnique => write!(f, "entity id has a malformed unique part"),
+            ParseIdError::BadTime => write!(f, "entity id has a malformed time part"),
+        }
+    }
+}
+
+impl std::error::Error for ParseIdError {}
+
+impl FromStr for EntityId {
+    type Err = ParseIdError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (unique, time) = s.trim().split_once('@').ok_or(ParseIdError::MissingTime)?;
+        if unique.is_empty() || unique.len() > 16 {
+            return Err(ParseIdError::BadUnique);
+        }
+        let unique = u64::from_str_radix(unique, 16).map_err(|_| ParseIdError::BadUnique)?;
+        let (secs, nanos) = time.split_once('.').unwrap_or((time, "0"));
+        let secs: u64 = secs.parse().map_err(|_| ParseIdError::BadTime)?;
+        let nanos: u32 = nanos.parse().ok().filter(|n| *n < 1_000_000_000).ok_or(ParseIdError::BadTime)?;
+        let time = UNIX_EPOCH.checked_add(Duration::new(secs, nanos)).ok_or(ParseIdError::BadTime)?;
+        Ok(EntityId { time, unique })
+    }
+}
+
+/// finds ids by a hex prefix of unique, like git short hashes;
+/// both the full 16-digit form and the unpadded one of Debug count.
+pub struct PrefixIndex<'a> {
+    by_hex: BTreeMap<String, &'a EntityId>,
+}
+
+impl<'a> PrefixIndex<'a> {
+    pub fn new(ids: impl Iterator<Item = &'a EntityId>) -> Self {
+        let mut by_hex = BTreeMap::new();
+        for id in ids {
+            by_hex.insert(format!("{:016x}", id.unique), id);
+            by_hex.insert(format!("{:x}", id.unique), id);
+        }
+        PrefixIndex { by_hex }
+    }
+    /// every id matching prefix, ignoring case and `[[..]]` around it.
+    pub fn find(&self, prefix: &str) -> Vec<&'a EntityId> {
+        let prefix = prefix.trim().trim_start_matches("[[").trim_end_matches("]]").to_lowercase();
+        if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Vec::new();
+        }
+        let mut res: Vec<&EntityId> = self.by_hex.range(prefix.clone()..)
+            .take_while(|(hex, _)| hex.starts_with(prefix.as_str()))
+            .map(|(_, id)| *id)
+            .collect();
+        res.sort_by_key(|id| (id.time, id.unique));
+        res.dedup();
+        res
+    }
+}
+
+/// time as in Compact for SystemTime; unique as 8 raw bytes since it's mostly random.
+impl Compact for EntityId {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.time.encode(buf);
+        buf.extend_from_slice(&self.unique.to_le_bytes());
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let time = SystemTime::decode(src)?;
+        let mut unique = [0; 8];
+        unique.copy_from_slice(decode_bytes(src, 8)?);
+        Ok(EntityId { time, unique: u64::from_le_bytes(unique) })
+    }
+}
+
+/// how EntityIdFactory::next_id makes ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdMode {
+    /// the current time and a random unique, as time_id.
+    #[default]
+    Random,
+    /// ulid-like: strictly increasing per factory, even if the clock goes back; opt in with set_mode.
+    Monotonic,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct EntityIdFactory {
-    cnt: u64
+    cnt: u64,
+    #[serde(default)]
+    mode: IdMode,
+    /// high-water mark: the last id made in Monotonic mode.
+    #[serde(default)]
+    last: Option<EntityId>,
+}
+
+/// cnt, mode, last.
+impl Compact for EntityIdFactory {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.cnt.encode(buf);
+        match self.mode {
+            IdMode::Random => 0u8,
+            IdMode::Monotonic => 1u8,
+        }.encode(buf);
+        self.last.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let cnt = u64::decode(src)?;
+        let mode = match u8::decode(src)? {
+            0 => IdMode::Random,
+            1 => IdMode::Monotonic,
+            t => return Err(CompactError::InvalidTag(t)),
+        };
+        Ok(EntityIdFactory { cnt, mode, last: Option::decode(src)? })
+    }
 }
 
 impl EntityIdFactory {
+    /// the layout before the high-water mark: cnt only.
+    pub(crate) fn decode_counter(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(EntityIdFactory { cnt: u64::decode(src)?, ..Self::default() })
+    }
+    pub fn with_mode(mode: IdMode) -> Self {
+        EntityIdFactory { mode, ..Self::default() }
+    }
+    pub fn mode(&self) -> IdMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: IdMode) {
+        self.mode = mode
+    }
     /// generate id by increment
     pub fn incr_id(&mut self) -> EntityId {
         self.cnt += 1;
@@ -67,6 +222,27 @@ impl EntityIdFactory {
             unique: rand::random()
         }
     }
+    /// an id as the mode says.
+    pub fn next_id(&mut self) -> EntityId {
+        match self.mode {
+            IdMode::Random => self.time_id(),
+            IdMode::Monotonic => self.monotonic_id(SystemTime::now()),
+        }
+    }
+    /// a fresh random unique when the clock moves forward, or the last one plus one when it doesn't;
+    /// the random part leaves the top bit clear so there's room to count up.
+    fn monotonic_id(&mut self, now: SystemTime) -> EntityId {
+        let fresh = |time| EntityId { time, unique: rand::random::<u64>() >> 1 };
+        let id = match &self.last {
+            Some(last) if now <= last.time => match last.unique.checked_add(1) {
+                Some(unique) => EntityId { time: last.time, unique },
+                None => fresh(last.time + Duration::from_nanos(1)),
+            },
+            _ => fresh(now),
+        };
+        self.last = Some(id.clone());
+        id
+    }
 }
 
 #[cfg(test)]
@@ -93,4 +269,64 @@ mod tests {
         println!("{:#?}", id_factory.time_id());
         println!("{:#?}", id_factory.time_id());
     }
+    #[test]
+    fn text() {
+        let id = EntityIdFactory::default().time_id();
+        let text = id.to_string();
+        assert_eq!(text.parse(), Ok(id));
+        let id = EntityId::from_parts(UNIX_EPOCH + Duration::new(1614000000, 5), 0xabc);
+        assert_eq!(id.to_string(), "0000000000000abc@1614000000.000000005");
+        assert_eq!("abc@1614000000.5".parse(), Ok(id));
+        assert_eq!("abc".parse::<EntityId>(), Err(ParseIdError::MissingTime));
+        assert_eq!("xyz@1".parse::<EntityId>(), Err(ParseIdError::BadUnique));
+        assert_eq!("abc@1.1000000000".parse::<EntityId>(), Err(ParseIdError::BadTime));
+    }
+    #[test]
+    fn prefix() {
+        let ids: Vec<EntityId> = [0xabc1, 0xabc2, 0x1f].iter().map(|u| EntityId::from_parts(UNIX_EPOCH, *u)).collect();
+        let index = PrefixIndex::new(ids.iter());
+        assert_eq!(index.find("abc").len(), 2);
+        assert_eq!(index.find("[[ABC2]]"), vec![&ids[1]]);
+        assert_eq!(index.find("1f"), vec![&ids[2]]);
+        assert_eq!(index.find("000000000000001"), vec![&ids[2]]);
+        assert!(index.find("0001x").is_empty());
+    }
+    #[test]
+    fn monotonic() {
+        let mut id_factory = EntityIdFactory::with_mode(IdMode::Monotonic);
+        let t = SystemTime::now();
+        let a = id_factory.monotonic_id(t);
+        let b = id_factory.monotonic_id(t);
+        // the clock goes back
+        let c = id_factory.monotonic_id(t - Duration::from_secs(60));
+        let d = id_factory.monotonic_id(t + Duration::from_secs(1));
+        assert!(a < b && b < c && c < d);
+        assert_eq!((b.time, b.unique), (a.time, a.unique + 1));
+        id_factory.last = Some(EntityId { time: t, unique: u64::MAX });
+        assert!(id_factory.monotonic_id(t) > EntityId { time: t, unique: u64::MAX });
+        let mut ids: Vec<EntityId> = (0..50).map(|_| id_factory.next_id()).collect();
+        let sorted = ids.clone();
+        ids.sort();
+        assert_eq!(ids, sorted);
+        // the high-water mark survives encoding
+        let _id_factory = EntityIdFactory::from_compact(&id_factory.to_compact()).unwrap();
+        assert_eq!(_id_factory.last, id_factory.last);
+    }
+    #[test]
+    fn mode() {
+        // factories saved before the mode existed stay random
+        let id_factory: EntityIdFactory = serde_json::from_str(r#"{"cnt":3}"#).unwrap();
+        assert_eq!(id_factory.mode(), IdMode::Random);
+        assert_eq!(EntityIdFactory::decode_counter(&mut 3u64.to_compact().as_slice()).unwrap().mode(), IdMode::Random);
+        let mut id_factory = EntityIdFactory::default();
+        id_factory.next_id();
+        assert_eq!(id_factory.last, None);
+    }
+    #[test]
+    fn compact() {
+        let id_factory = EntityIdFactory::default();
+        let id = id_factory.time_id();
+        assert_eq!(EntityId::from_compact(&id.to_compact()), Ok(id));
+        assert_eq!(EntityId::from_compact(&EntityId::default().to_compact()), Ok(EntityId::default()));
+    }
 }
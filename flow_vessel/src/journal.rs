@@ -0,0 +1,522 @@
+//! journal.rs contains an append-only store for a Vessel, one JSON line per record:
+//! 1. a checkpoint holds the whole vessel, as Vessel::to_json writes it;
+//! 2. a batch holds the mutations of one commit: entities put in place or removed, entries of the trash
+//!    and the archive put or removed, and settings.
+//!
+//! opening replays the batches after the last checkpoint; compaction rewrites the file
+//! as a single checkpoint, the same way Vessel::save does. a final line cut short by a crash is dropped.
+//!
+//! edits are collected by Vessel::touch, so a batch carries the final state of each touched entity
+//! rather than the steps that led there.
+
+use std::{collections::HashSet, fs, io::{self, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+use serde::{Serialize, Deserialize};
+use flow_arena::{Flow, FlowArena, Node};
+
+use super::Vessel;
+use super::identity::{EntityId, EntityIdFactory};
+use super::entity::Entity;
+use super::process::TransitionTable;
+use super::trash::{Subtree, Trashed};
+use super::archive::Archived;
+use super::persist::{PersistError, temp_path};
+
+/// batches between automatic compactions, by default.
+const COMPACT_EVERY: usize = 256;
+
+/// what changed since the last commit.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Changes {
+    pub(crate) touched: HashSet<EntityId>,
+    /// the transition table, the id mode or the trash retention.
+    pub(crate) settings: bool,
+    /// entries of the trash added or removed, by id.
+    pub(crate) trashed: HashSet<EntityId>,
+    /// entries of the archive added or removed, by id.
+    pub(crate) archived: HashSet<EntityId>,
+}
+
+impl Changes {
+    pub(crate) fn touch(&mut self, id: &EntityId) {
+        self.touched.insert(id.clone());
+    }
+    pub(crate) fn merge(&mut self, other: Changes) {
+        self.touched.extend(other.touched);
+        self.settings |= other.settings;
+        self.trashed.extend(other.trashed);
+        self.archived.extend(other.archived);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Place {
+    /// the nth child of parent; for the children of root, parent is root.
+    Under { parent: EntityId, nth: usize },
+    /// a named top-level tree, the nth in the forest; at the end if nth is None, as in older journals.
+    Planted {
+        name: String,
+        #[serde(default)]
+        nth: Option<usize>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum Mutation {
+    Put { entity: Entity, place: Place },
+    Remove { id: EntityId },
+    Transitions(TransitionTable),
+    Factory(EntityIdFactory),
+    /// the entry as the nth of the trash.
+    TrashPut { entry: Trashed, nth: usize },
+    TrashRemove { id: EntityId },
+    Retention(Option<Duration>),
+    /// the entry with its subtree as the nth of the archive.
+    ArchivePut { entry: Archived, nodes: Subtree, nth: usize },
+    ArchiveRemove { id: EntityId },
+}
+
+#[derive(Serialize)]
+enum RecordRef<'a> {
+    Checkpoint(&'a Vessel),
+    Batch(&'a [Mutation]),
+}
+
+#[derive(Deserialize)]
+enum Record {
+    Checkpoint(Box<Vessel>),
+    Batch(Vec<Mutation>),
+}
+
+/// puts for the ids in arena and removals for the ids not in it:
+/// removals first, then puts in an order that replays.
+pub(crate) fn mutations(arena: &mut FlowArena<EntityId, Entity>, ids: impl IntoIterator<Item = EntityId>) -> Vec<Mutation> {
+    let root = arena.root.clone();
+    let mut ids: Vec<EntityId> = ids.into_iter().filter(|id| id != &root).collect();
+    ids.sort();
+    let mut removed = Vec::new();
+    let mut put = Vec::new();
+    for id in ids {
+        if !arena.materialize(&id).unwrap_or(false) {
+            removed.push(Mutation::Remove { id });
+            continue
+        }
+        let node = &arena.node_map[&id];
+        let (key, place) = match &node.parent {
+            Some(parent) => {
+                let nth = arena.node_map.get(parent)
+                    .and_then(|parent| parent.children.iter().position(|x| x == &id))
+                    .unwrap_or_default();
+                ((1, nth), Place::Under { parent: parent.clone(), nth })
+            }
+            None => {
+                let nth = arena.forest.iter().position(|tree| tree.id == id).unwrap_or_default();
+                ((0, nth), Place::Planted { name: arena.forest[nth].name.clone(), nth: Some(nth) })
+            }
+        };
+        put.push((key, Mutation::Put { entity: node.entity.clone(), place }));
+    }
+    // Note: siblings go in left to right, so that each nth is right when it's placed.
+    put.sort_by_key(|(key, _)| *key);
+    let mut batch = removed;
+    batch.extend(put.into_iter().map(|(_, put)| put));
+    batch
+}
+
+impl Vessel {
+    /// the changes since the last call as mutations, then the settings.
+    pub(crate) fn take_changes(&mut self) -> Vec<Mutation> {
+        let changes = std::mem::take(&mut self.changes);
+        let mut batch = mutations(&mut self.flow_arena, changes.touched);
+        if changes.settings {
+            batch.push(Mutation::Transitions(self.transitions.clone()));
+            batch.push(Mutation::Retention(self.trash.retention));
+        }
+        batch.extend(self.trash.mutations(changes.trashed));
+        batch.extend(self.archive.mutations(changes.archived));
+        if !batch.is_empty() {
+            batch.push(Mutation::Factory(self.id_factory.clone()));
+        }
+        batch
+    }
+    /// replays a batch made by take_changes.
+    pub(crate) fn apply_batch(&mut self, batch: Vec<Mutation>) {
+        let root = self.flow_arena.root.clone();
+        let mut placing = Vec::new();
+        for mutation in batch {
+            match mutation {
+                Mutation::Remove { id } => if self.flow_arena.materialize(&id).unwrap_or(false) {
+                    self.flow_arena.page_in(&id).ok();
+                    self.flow_arena.decay(&id).ok();
+                }
+                Mutation::Put { entity, place } => {
+                    let id = entity.id().clone();
+                    let exists = self.flow_arena.materialize(&id).unwrap_or(false);
+                    match (exists, &place) {
+                        (true, _) => {
+                            self.flow_arena.node_map.get_mut(&id).expect("materialized").entity = entity;
+                        }
+                        (false, Place::Planted { name, nth }) => {
+                            let nth = nth.map_or(self.flow_arena.forest.len(), |nth| nth.min(self.flow_arena.forest.len()));
+                            self.flow_arena.plant_at(name, Node::from_id(id.clone(), entity), nth).ok();
+                        }
+                        (false, Place::Under { .. }) => { self.flow_arena.grow(Node::from_id(id.clone(), entity)).ok(); }
+                    }
+                    if let Place::Under { parent, nth } = place {
+                        // Note: cut to the end of root first, so that old places don't shift the new ones.
+                        if exists {
+                            let len = self.flow_arena.node_map[&root].children.iter().filter(|x| *x != &id).count();
+                            self.flow_arena.relocate(&id, &root, len).ok();
+                        }
+                        placing.push((id, parent, nth));
+                    }
+                }
+                Mutation::Transitions(table) => self.transitions = table,
+                Mutation::Factory(factory) => self.id_factory = factory,
+                Mutation::TrashPut { entry, nth } => self.trash.put(entry, nth),
+                Mutation::TrashRemove { id } => self.trash.remove(&id),
+                Mutation::Retention(retention) => self.trash.retention = retention,
+                Mutation::ArchivePut { entry, nodes, nth } => self.archive.put(entry, nodes, nth),
+                Mutation::ArchiveRemove { id } => self.archive.remove(&id),
+            }
+        }
+        for (id, parent, nth) in placing {
+            self.flow_arena.materialize(&parent).ok();
+            self.flow_arena.page_in(&parent).ok();
+            self.flow_arena.relocate(&id, &parent, nth).ok();
+        }
+    }
+}
+
+/// the file a Vessel is journaled to; see journal.rs.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: fs::File,
+    /// batches appended since the last checkpoint.
+    batches: usize,
+    /// compacts once this many batches pile up; 0 never does.
+    compact_every: usize,
+}
+
+fn append(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Journal {
+    /// opens the journal at path, creating it if missing, and replays it.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Journal, Vessel), PersistError> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut vessel = None;
+        let mut batches = 0;
+        // the end of the last line read whole
+        let mut good = 0;
+        let mut start = 0;
+        for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+            let end = start + line.len();
+            let last = end >= bytes.len();
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                match serde_json::from_slice::<Record>(line) {
+                    Ok(Record::Checkpoint(checkpoint)) => {
+                        vessel = Some(*checkpoint);
+                        batches = 0;
+                    }
+                    Ok(Record::Batch(batch)) => {
+                        vessel.get_or_insert_with(Vessel::new).apply_batch(batch);
+                        batches += 1;
+                    }
+                    // Note: a write cut short; everything before it stands.
+                    Err(e) if last && e.is_eof() => break,
+                    Err(_) => return Err(PersistError::Corrupt { line: i + 1 }),
+                }
+            }
+            good = if last { end } else { end + 1 };
+            start = end + 1;
+        }
+        let mut file = append(&path)?;
+        if good < bytes.len() {
+            file.set_len(good as u64)?;
+        } else if !bytes.ends_with(b"\n") && !bytes.is_empty() {
+            file.write_all(b"\n")?;
+        }
+        let mut journal = Journal { path, file, batches, compact_every: COMPACT_EVERY };
+        let mut vessel = match vessel {
+            Some(vessel) => vessel,
+            None => {
+                let mut vessel = Vessel::new();
+                journal.compact(&mut vessel)?;
+                vessel
+            }
+        };
+        vessel.search = Default::default();
+        vessel.links = Default::default();
+        vessel.changes = Default::default();
+        // Note: the purge is journaled with the next commit.
+        vessel.trash_purge(SystemTime::now());
+        Ok((journal, vessel))
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// compacts once n batches pile up; 0 never does.
+    pub fn set_compact_every(&mut self, n: usize) {
+        self.compact_every = n;
+    }
+    /// appends the changes to vessel since the last commit as one line, flushed to disk;
+    /// returns the number of mutations written, 0 if nothing changed.
+    pub fn commit(&mut self, vessel: &mut Vessel) -> Result<usize, PersistError> {
+        let changes = vessel.changes.clone();
+        let batch = vessel.take_changes();
+        if batch.is_empty() { return Ok(0) }
+        let res = (|| {
+            let mut line = serde_json::to_string(&RecordRef::Batch(&batch))?;
+            line.push('\n');
+            let len = self.file.metadata()?.len();
+            if let Err(e) = self.file.write_all(line.as_bytes()).and_then(|_| self.file.sync_data()) {
+                // Note: cut off a torn line, or it ends up mid-file once the next commit lands.
+                self.file.set_len(len).ok();
+                return Err(e.into());
+            }
+            Ok(())
+        })();
+        if let Err(e) = res {
+            // Note: keep them for the next commit.
+            vessel.changes.merge(changes);
+            return Err(e);
+        }
+        self.batches += 1;
+        if self.compact_every > 0 && self.batches >= self.compact_every {
+            self.compact(vessel)?;
+        }
+        Ok(batch.len())
+    }
+    /// rewrites the journal as a single checkpoint of vessel; a crash midway leaves the old one intact.
+    pub fn compact(&mut self, vessel: &mut Vessel) -> Result<(), PersistError> {
+        let mut line = serde_json::to_string(&RecordRef::Checkpoint(vessel))?;
+        line.push('\n');
+        let tmp = temp_path(&self.path);
+        let res = (|| {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(line.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)?;
+            append(&self.path)
+        })();
+        match res {
+            Ok(file) => self.file = file,
+            Err(e) => {
+                fs::remove_file(&tmp).ok();
+                return Err(e.into());
+            }
+        }
+        self.batches = 0;
+        vessel.changes = Default::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessStatus;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flow_vessel-journal-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn assert_same(vessel: &Vessel, other: &Vessel) {
+        assert_eq!(vessel.flow_arena, other.flow_arena);
+        assert_eq!(vessel.transitions, other.transitions);
+        assert_eq!(format!("{:?}", vessel.id_factory), format!("{:?}", other.id_factory));
+    }
+
+    #[test]
+    fn replay() {
+        let dir = temp_dir("replay");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        let a = vessel.entity_grow();
+        let b = vessel.entity_grow();
+        let c = vessel.entity_grow();
+        vessel.import_outline(Some(&a), "- x\n  - y\n- z\n", false).unwrap();
+        vessel.entity_get_mut(&b).unwrap().face = "bee".to_string();
+        assert!(journal.commit(&mut vessel).unwrap() > 0);
+        assert_eq!(journal.commit(&mut vessel).unwrap(), 0);
+
+        // moves, removals with children, plants and settings
+        vessel.entity_move(&c, Some(&b)).unwrap();
+        vessel.entity_move(&b, Some(&a)).unwrap();
+        let x = vessel.entity_children(&a)[0].clone();
+        vessel.entity_decay(&x);
+        let work = vessel.root_plant("work").unwrap();
+        vessel.entity_transplant(&c, &work).unwrap();
+        // a root planted in between keeps its place
+        vessel.root_plant("home").unwrap();
+        vessel.entity_get_mut(&work).unwrap().schedule.scheduled = Some(SystemTime::now());
+        vessel.entity_get_mut(&work).unwrap().schedule.recurrence = Some(crate::Recurrence::Daily);
+        vessel.entity_transit(&work, ProcessStatus::Done).unwrap();
+        vessel.transition_table_mut().forbid(ProcessStatus::New, ProcessStatus::Done);
+        vessel.entity_transit(&a, ProcessStatus::Marching).unwrap();
+        journal.commit(&mut vessel).unwrap();
+        drop(journal);
+
+        let (_, replayed) = Journal::open(&path).unwrap();
+        assert_same(&vessel, &replayed);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replay_tag_rename() {
+        let dir = temp_dir("tag_rename");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        let a = vessel.entity_grow();
+        vessel.entity_get_mut(&a).unwrap().tags.push("home".to_string());
+        journal.commit(&mut vessel).unwrap();
+        assert_eq!(vessel.tag_rename("home", "house"), 1);
+        assert!(journal.commit(&mut vessel).unwrap() > 0);
+        drop(journal);
+
+        let (_, mut replayed) = Journal::open(&path).unwrap();
+        assert_same(&vessel, &replayed);
+        assert_eq!(replayed.tag_query("house").unwrap(), vec![a.clone()]);
+        // and a transaction can undo it
+        let (_, record) = replayed.transaction(|tx| Ok::<_, ()>(tx.tag_rename("house", "home"))).unwrap();
+        assert_eq!(record.ids(), vec![a.clone()]);
+        replayed.undo(&record);
+        assert_eq!(replayed.tag_query("house").unwrap(), vec![a]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replay_trash() {
+        let dir = temp_dir("trash");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        let ids: Vec<EntityId> = (0..3).map(|i| {
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).unwrap().face = format!("entity number {}", i);
+            id
+        }).collect();
+        let leaf = vessel.entity_grow();
+        vessel.entity_move(&leaf, Some(&ids[2])).unwrap();
+        vessel.entity_trash(&ids[0]);
+        journal.commit(&mut vessel).unwrap();
+
+        // each batch carries the entries it changed, not the whole trash
+        vessel.entity_trash(&ids[1]);
+        journal.commit(&mut vessel).unwrap();
+        let last = fs::read_to_string(&path).unwrap().lines().last().unwrap().to_string();
+        assert!(last.contains("entity number 1") && !last.contains("entity number 0"));
+        vessel.trash_restore(&ids[0]).unwrap();
+        journal.commit(&mut vessel).unwrap();
+        drop(journal);
+
+        let (_, mut replayed) = Journal::open(&path).unwrap();
+        assert_same(&vessel, &replayed);
+        assert_eq!(replayed.trash, vessel.trash);
+        // and a transaction undoes a trash entry by entry
+        let (_, record) = replayed.transaction(|tx| Ok::<_, ()>(tx.entity_trash(&ids[2]))).unwrap();
+        replayed.undo(&record);
+        assert_eq!(replayed.trash, vessel.trash);
+        assert_eq!(replayed.entity_children(&ids[2]), vec![leaf]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replay_archive() {
+        let dir = temp_dir("archive");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        let ids: Vec<EntityId> = (0..3).map(|i| {
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).unwrap().face = format!("entity number {}", i);
+            vessel.entity_transit(&id, ProcessStatus::Done).unwrap();
+            id
+        }).collect();
+        let leaf = vessel.entity_grow();
+        vessel.entity_move(&leaf, Some(&ids[1])).unwrap();
+        vessel.entity_transit(&leaf, ProcessStatus::Done).unwrap();
+        vessel.entity_archive(&ids[0], SystemTime::now());
+        journal.commit(&mut vessel).unwrap();
+
+        // each batch carries the entries it changed, not the whole archive
+        vessel.entity_archive(&ids[1], SystemTime::now());
+        journal.commit(&mut vessel).unwrap();
+        let last = fs::read_to_string(&path).unwrap().lines().last().unwrap().to_string();
+        assert!(last.contains("entity number 1") && !last.contains("entity number 0"));
+        vessel.entity_unarchive(&ids[0]).unwrap();
+        vessel.entity_archive(&ids[2], SystemTime::now());
+        journal.commit(&mut vessel).unwrap();
+        drop(journal);
+
+        let (_, mut replayed) = Journal::open(&path).unwrap();
+        assert_same(&vessel, &replayed);
+        assert_eq!(replayed.archive_list(), vessel.archive_list());
+        assert_eq!(replayed.archive_children(&ids[1]), vec![leaf.clone()]);
+        // and a transaction undoes an unarchive entry by entry
+        let (_, record) = replayed.transaction(|tx| tx.entity_unarchive(&ids[1])).unwrap();
+        assert_eq!(replayed.entity_children(&ids[1]), vec![leaf.clone()]);
+        replayed.undo(&record);
+        assert_eq!(replayed.archive_list(), vessel.archive_list());
+        assert_eq!(replayed.archive_children(&ids[1]), vec![leaf]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn recovery() {
+        let dir = temp_dir("recovery");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        let a = vessel.entity_grow();
+        journal.commit(&mut vessel).unwrap();
+        vessel.entity_get_mut(&a).unwrap().face = "cut short".to_string();
+        journal.commit(&mut vessel).unwrap();
+        drop(journal);
+
+        // the last write stops halfway
+        let bytes = fs::read(&path).unwrap();
+        let whole = bytes.len() - 30;
+        fs::write(&path, &bytes[..whole]).unwrap();
+        let (mut journal, mut replayed) = Journal::open(&path).unwrap();
+        assert_eq!(replayed.entity_get(&a).unwrap().face, "");
+        // and the next commit starts on a clean line
+        replayed.entity_get_mut(&a).unwrap().face = "again".to_string();
+        journal.commit(&mut replayed).unwrap();
+        drop(journal);
+        let (_, again) = Journal::open(&path).unwrap();
+        assert_same(&replayed, &again);
+
+        // a broken line in the middle is not a crash
+        let mut lines: Vec<String> = fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        lines[1].truncate(10);
+        fs::write(&path, lines.join("\n")).unwrap();
+        assert!(matches!(Journal::open(&path), Err(PersistError::Corrupt { line: 2 })));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn compaction() {
+        let dir = temp_dir("compaction");
+        let path = dir.join("vessel.journal");
+        let (mut journal, mut vessel) = Journal::open(&path).unwrap();
+        journal.set_compact_every(2);
+        let a = vessel.entity_grow();
+        journal.commit(&mut vessel).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        vessel.entity_get_mut(&a).unwrap().face = "kept".to_string();
+        journal.commit(&mut vessel).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert!(!temp_path(&path).exists());
+        drop(journal);
+        let (_, replayed) = Journal::open(&path).unwrap();
+        assert_same(&vessel, &replayed);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
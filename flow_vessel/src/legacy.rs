@@ -0,0 +1,353 @@
+//! legacy.rs imports the app's old `Vessel` json (stored under `flow.er.data`):
+//! ```text
+//! { "entity_map": { "<u64>": { "id", "time": [secs, nanos], "face", "bubble", "process", "tags": { "data" } } },
+//!   "flow": { "map": { "<u64>": { "descendant": [u64], "owner": u64 | null } }, "roots": [u64], .. }, .. }
+//! ```
+//! owners become parents, roots become children of root, and each old id `x` becomes
+//! an EntityId with the old time stamp and `x` as its unique part, so old `[[..]]` refs still read the same;
+//! the stamp also becomes a TimeLog with one closed session of no length.
+//! anything inconsistent is repaired or dropped and recorded in the ImportReport.
+
+use std::{collections::{HashMap, HashSet}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use serde::Deserialize;
+use flow_arena::{Flow, Node};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::time::TimeLog;
+use super::PersistError;
+
+type LegacyId = u64;
+
+#[derive(Deserialize)]
+struct LegacyVessel {
+    #[serde(default)]
+    entity_map: HashMap<LegacyId, LegacyEntity>,
+    #[serde(default)]
+    flow: LegacyFlow,
+}
+
+#[derive(Deserialize)]
+struct LegacyEntity {
+    #[serde(default)]
+    time: Option<(u64, u32)>,
+    #[serde(default)]
+    face: String,
+    #[serde(default)]
+    bubble: String,
+    #[serde(default)]
+    process: Option<String>,
+    #[serde(default)]
+    tags: LegacyTagSet,
+}
+
+#[derive(Default, Deserialize)]
+struct LegacyTagSet {
+    #[serde(default)]
+    data: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct LegacyFlow {
+    #[serde(default)]
+    map: HashMap<LegacyId, LegacyFlowNode>,
+    #[serde(default)]
+    roots: Vec<LegacyId>,
+}
+
+#[derive(Default, Deserialize)]
+struct LegacyFlowNode {
+    #[serde(default)]
+    descendant: Vec<LegacyId>,
+    #[serde(default)]
+    owner: Option<LegacyId>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LegacyIssue {
+    /// dropped: a flow node without entity; its children move up to its owner.
+    MissingEntity,
+    /// repaired: an entity without flow node, put under root.
+    MissingFlowNode,
+    /// repaired: the owner doesn't exist, put under root.
+    MissingOwner(LegacyId),
+    /// repaired: the owner chain loops back, cut and put under root.
+    Cycle,
+    /// repaired: not listed in its owner's descendant, appended to it.
+    NotInDescendant(LegacyId),
+    /// repaired: listed in the descendant of a node that doesn't own it, unlisted there.
+    StrayDescendant(LegacyId),
+    /// repaired: without owner but not in roots, appended to roots.
+    NotInRoots,
+    /// dropped: listed in roots but owned by another node or missing.
+    StrayRoot,
+    /// repaired: the time stamp is out of range, left empty.
+    InvalidTime,
+    /// repaired: an unknown process status, set to New.
+    UnknownProcess(String),
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// old id -> new id, for every imported entity.
+    pub id_map: HashMap<LegacyId, EntityId>,
+    pub dropped: Vec<(LegacyId, LegacyIssue)>,
+    pub repaired: Vec<(LegacyId, LegacyIssue)>,
+}
+
+impl ImportReport {
+    pub fn is_clean(&self) -> bool {
+        self.dropped.is_empty() && self.repaired.is_empty()
+    }
+}
+
+fn stamp_to_time((secs, nanos): (u64, u32)) -> Option<SystemTime> {
+    if nanos >= 1_000_000_000 { return None }
+    UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+impl Vessel {
+    /// imports the app's old vessel json; see legacy.rs.
+    pub fn import_legacy(json: &str) -> Result<(Vessel, ImportReport), PersistError> {
+        let legacy: LegacyVessel = serde_json::from_str(json)?;
+        Ok(Self::from_legacy(legacy))
+    }
+    fn from_legacy(legacy: LegacyVessel) -> (Vessel, ImportReport) {
+        let LegacyVessel { entity_map, flow } = legacy;
+        let mut report = ImportReport::default();
+        // sorted for a stable outcome
+        let mut keys: Vec<LegacyId> = entity_map.keys().cloned().collect();
+        keys.sort_unstable();
+        let mut flow_keys: Vec<LegacyId> = flow.map.keys().cloned().collect();
+        flow_keys.sort_unstable();
+
+        // owners, skipping flow nodes without entity
+        let mut parent: HashMap<LegacyId, Option<LegacyId>> = HashMap::new();
+        for key in flow_keys.iter() {
+            if !entity_map.contains_key(key) {
+                report.dropped.push((*key, LegacyIssue::MissingEntity));
+            }
+        }
+        let lift = |mut owner: Option<LegacyId>| {
+            // Note: an owner without entity hands its children up.
+            let mut seen = HashSet::new();
+            while let Some(o) = owner {
+                if entity_map.contains_key(&o) || !seen.insert(o) { break }
+                owner = flow.map.get(&o).and_then(|node| node.owner);
+            }
+            owner
+        };
+        for key in keys.iter() {
+            let owner = match flow.map.get(key) {
+                Some(node) => lift(node.owner),
+                None => {
+                    report.repaired.push((*key, LegacyIssue::MissingFlowNode));
+                    None
+                }
+            };
+            let owner = match owner {
+                Some(o) if !entity_map.contains_key(&o) => {
+                    report.repaired.push((*key, LegacyIssue::MissingOwner(o)));
+                    None
+                }
+                owner => owner,
+            };
+            parent.insert(*key, owner);
+        }
+        // cycles
+        for key in keys.iter() {
+            let mut seen = HashSet::new();
+            let mut cur = *key;
+            seen.insert(cur);
+            while let Some(Some(p)) = parent.get(&cur) {
+                if !seen.insert(*p) {
+                    report.repaired.push((cur, LegacyIssue::Cycle));
+                    parent.insert(cur, None);
+                    break
+                }
+                cur = *p;
+            }
+        }
+
+        // children in descendant order, then the ones missing from it
+        let mut children: HashMap<LegacyId, Vec<LegacyId>> = HashMap::new();
+        for key in keys.iter() {
+            let listed: Vec<LegacyId> = flow.map.get(key).map(|node| node.descendant.clone()).unwrap_or_default();
+            let mut vec = Vec::new();
+            for child in listed {
+                match parent.get(&child) {
+                    Some(Some(p)) if p == key && vec.contains(&child) => (),
+                    Some(Some(p)) if p == key => vec.push(child),
+                    Some(_) => report.repaired.push((child, LegacyIssue::StrayDescendant(*key))),
+                    // Note: children of dropped nodes are picked up below.
+                    None => (),
+                }
+            }
+            children.insert(*key, vec);
+        }
+        for key in keys.iter() {
+            if let Some(Some(p)) = parent.get(key) {
+                let vec = children.get_mut(p).expect("owner checked");
+                if !vec.contains(key) {
+                    if flow.map.get(p).is_some_and(|node| !node.descendant.contains(key)) {
+                        report.repaired.push((*key, LegacyIssue::NotInDescendant(*p)));
+                    }
+                    vec.push(*key);
+                }
+            }
+        }
+        let mut roots = Vec::new();
+        for root in flow.roots.iter() {
+            if parent.get(root) == Some(&None) && !roots.contains(root) {
+                roots.push(*root)
+            } else if !roots.contains(root) {
+                report.dropped.push((*root, LegacyIssue::StrayRoot));
+            }
+        }
+        for key in keys.iter() {
+            if parent.get(key) == Some(&None) && !roots.contains(key) {
+                if flow.map.contains_key(key) {
+                    report.repaired.push((*key, LegacyIssue::NotInRoots));
+                }
+                roots.push(*key);
+            }
+        }
+
+        // entities
+        let mut vessel = Vessel::new();
+        let mut entities: HashMap<LegacyId, Entity> = HashMap::new();
+        for (key, legacy) in entity_map.into_iter() {
+            let stamp = legacy.time.map(|stamp| (stamp, stamp_to_time(stamp)));
+            let id = EntityId::from_parts(stamp.and_then(|(_, t)| t).unwrap_or(UNIX_EPOCH), key);
+            let mut entity = Entity::new_id(&id);
+            match stamp {
+                // Note: the stamp is when the entity was made, not time worked on it;
+                // a closed session of no length keeps it out of the totals and the agenda.
+                Some((_, Some(t))) => {
+                    let mut time = TimeLog::new();
+                    time.set_start(t).set_end(t);
+                    entity.time = Some(time);
+                }
+                Some((_, None)) => report.repaired.push((key, LegacyIssue::InvalidTime)),
+                None => (),
+            }
+            entity.face = legacy.face;
+            entity.bubble = legacy.bubble;
+            entity.process = match legacy.process {
+                Some(p) if ProcessStatus::vec_all().iter().any(|x| x.type_str() == p) => ProcessStatus::reflect(&p),
+                Some(p) => {
+                    report.repaired.push((key, LegacyIssue::UnknownProcess(p)));
+                    ProcessStatus::New
+                }
+                None => ProcessStatus::New,
+            };
+            legacy.tags.data.into_iter().for_each(|tag| { entity.tags.push(tag); });
+            report.id_map.insert(key, id);
+            entities.insert(key, entity);
+        }
+
+        // structure, breadth first from roots
+        let mut queue: Vec<LegacyId> = roots.clone();
+        for key in roots.iter() {
+            let entity = entities.remove(key).expect("root has entity");
+            vessel.flow_arena.grow(Node::from_id(report.id_map[key].clone(), entity)).ok();
+        }
+        let mut head = 0;
+        while head < queue.len() {
+            let key = queue[head];
+            head += 1;
+            for child in children.get(&key).cloned().unwrap_or_default() {
+                let entity = match entities.remove(&child) {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let id = report.id_map[&child].clone();
+                vessel.flow_arena.grow(Node::from_id(id.clone(), entity)).ok();
+                vessel.flow_arena.devote_push(&id, &report.id_map[&key]).ok();
+                queue.push(child);
+            }
+        }
+        (vessel, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Session;
+
+    #[test]
+    fn import() {
+        // 1 -> [2 -> [3]], 4; 5 has no entity and owns 6; 7 has no flow node; 8 <-> 9 loop;
+        // 10 is owned by 1 but not listed; 11 is not in roots.
+        let json = r#"{
+            "entity_map": {
+                "1": { "id": 1, "time": [1614000000, 0], "face": "one", "bubble": "", "process": "Marching", "tags": { "data": ["a"] } },
+                "2": { "id": 2, "time": [1614000001, 0], "face": "two", "bubble": "b", "process": "Done", "tags": { "data": [] } },
+                "3": { "id": 3, "time": [1614000002, 0], "face": "three", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "4": { "id": 4, "time": [1614000003, 0], "face": "four", "bubble": "", "process": "Whatever", "tags": { "data": [] } },
+                "6": { "id": 6, "time": [1614000004, 0], "face": "six", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "7": { "id": 7, "time": [1614000005, 0], "face": "seven", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "8": { "id": 8, "time": [1614000006, 0], "face": "eight", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "9": { "id": 9, "time": [1614000007, 0], "face": "nine", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "10": { "id": 10, "time": [1614000008, 0], "face": "ten", "bubble": "", "process": "New", "tags": { "data": [] } },
+                "11": { "id": 11, "time": [1614000009, 0], "face": "eleven", "bubble": "", "process": "New", "tags": { "data": [] } }
+            },
+            "flow": {
+                "map": {
+                    "1": { "descendant": [2], "owner": null },
+                    "2": { "descendant": [3], "owner": 1 },
+                    "3": { "descendant": [], "owner": 2 },
+                    "4": { "descendant": [], "owner": null },
+                    "5": { "descendant": [6], "owner": 1 },
+                    "6": { "descendant": [], "owner": 5 },
+                    "8": { "descendant": [9], "owner": 9 },
+                    "9": { "descendant": [8], "owner": 8 },
+                    "10": { "descendant": [], "owner": 1 },
+                    "11": { "descendant": [], "owner": null }
+                },
+                "roots": [4, 1, 42],
+                "pos": null,
+                "fix": "Deactivated"
+            },
+            "router": "Cube",
+            "vm_info": {}
+        }"#;
+        let (mut vessel, report) = Vessel::import_legacy(json).unwrap();
+        println!("{:#?}", report);
+        println!("{:#?}", vessel);
+        assert_eq!(report.id_map.len(), 10);
+        assert!(report.dropped.contains(&(5, LegacyIssue::MissingEntity)));
+        assert!(report.dropped.contains(&(42, LegacyIssue::StrayRoot)));
+        assert!(report.repaired.contains(&(7, LegacyIssue::MissingFlowNode)));
+        assert!(report.repaired.contains(&(4, LegacyIssue::UnknownProcess("Whatever".to_string()))));
+        assert!(report.repaired.contains(&(10, LegacyIssue::NotInDescendant(1))));
+        assert!(report.repaired.contains(&(11, LegacyIssue::NotInRoots)));
+        assert!(report.repaired.iter().any(|(_, issue)| *issue == LegacyIssue::Cycle));
+        let id = |x: u64| report.id_map[&x].clone();
+        let faces = |vessel: &mut Vessel, x: &EntityId| -> Vec<String> {
+            vessel.entity_list(x).into_iter().map(|e| e.face.clone()).collect()
+        };
+        let root = vessel.flow_arena.root.clone();
+        assert_eq!(&faces(&mut vessel, &root)[..2], &["four".to_string(), "one".to_string()]);
+        // 6 moves up from the dropped 5 to 1
+        assert_eq!(faces(&mut vessel, &id(1)), vec!["two".to_string(), "six".to_string(), "ten".to_string()]);
+        assert_eq!(faces(&mut vessel, &id(2)), vec!["three".to_string()]);
+        let one = vessel.entity_get(&id(1)).unwrap();
+        assert_eq!(one.process, ProcessStatus::Marching);
+        assert_eq!(one.tags.into_iter().collect::<Vec<_>>(), vec!["a".to_string()]);
+        let stamp = UNIX_EPOCH + Duration::from_secs(1614000000);
+        assert_eq!(one.time.as_ref().unwrap().sessions(), &[Session { start: stamp, end: Some(stamp) }]);
+        assert_eq!(vessel.time_total(&id(1), stamp + Duration::from_secs(60)), Duration::ZERO);
+        assert_eq!(format!("{:?}", id(10)), "[[a]]");
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(Vessel::import_legacy("[1, 2]"), Err(PersistError::Json(_))));
+        let (vessel, report) = Vessel::import_legacy("{}").unwrap();
+        assert!(report.is_clean());
+        assert_eq!(vessel.flow_arena.node_map.len(), 1);
+    }
+}
@@ -2,16 +2,66 @@ mod identity;
 mod entity;
 mod time;
 mod tag;
+mod compact;
+mod persist;
+mod legacy;
+mod search;
+mod process;
+mod schedule;
+mod agenda;
+mod links;
+mod markdown;
+mod opml;
+mod todotxt;
+mod ics;
+mod journal;
+mod transaction;
+mod trash;
+mod archive;
+#[cfg(test)]
+mod testing;
 
-use flow_arena::{Flow, FlowArena, Node};
-use identity::{EntityId, EntityIdFactory};
+use flow_arena::{Flow, FlowArena, Node, CompactError};
+use identity::EntityIdFactory;
 use entity::Entity;
 use std::fmt::Debug;
 
+pub use flow_arena::ForestError;
+pub use identity::{EntityId, ParseIdError, IdMode};
+pub use persist::{PersistError, SCHEMA_VERSION};
+pub use legacy::{ImportReport, LegacyIssue};
+pub use search::{SearchHit, Snippet, Field};
+pub use tag::{Tag, TagSet, TagQuery, TagQueryError};
+pub use entity::ProcessStatus;
+pub use time::{TimeLog, Session, TimerError};
+pub use schedule::{Schedule, Recurrence};
+pub use agenda::{Agenda, AgendaDay, AgendaItem, AgendaKind};
+pub use links::{Link, LinkTarget};
+pub use markdown::{MarkdownOptions, OutlineItem, OutlineError, parse_outline};
+pub use opml::parse_opml;
+pub use journal::Journal;
+pub use transaction::ChangeRecord;
+pub use trash::{Trashed, TrashError, TRASH_RETENTION};
+pub use archive::{Archived, ArchiveError};
+pub use process::{Transition, TransitionTable, TransitError, week_of};
+
 pub struct Vessel {
     flow_arena: FlowArena<EntityId, Entity>,
 
-    id_factory: EntityIdFactory
+    id_factory: EntityIdFactory,
+    /// allowed ProcessStatus moves for entity_transit.
+    transitions: process::TransitionTable,
+    /// trashed subtrees; see trash.rs.
+    trash: trash::Trash,
+    /// archived subtrees, out of the tree; see archive.rs.
+    archive: archive::Archive,
+
+    /// rebuilt on demand; never persisted.
+    search: search::SearchIndex,
+    /// rebuilt on demand; never persisted.
+    links: links::LinkIndex,
+    /// edits since the last journal commit; never persisted.
+    changes: journal::Changes,
 }
 
 impl Vessel {
@@ -19,6 +69,12 @@ impl Vessel {
         Self {
             flow_arena: FlowArena::new(),
             id_factory: EntityIdFactory::default(),
+            transitions: process::TransitionTable::default(),
+            trash: trash::Trash::default(),
+            archive: archive::Archive::default(),
+            search: search::SearchIndex::default(),
+            links: links::LinkIndex::default(),
+            changes: journal::Changes::default(),
         }
     }
 }
@@ -27,49 +83,254 @@ impl Debug for Vessel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Vessel")
          .field("root", &self.flow_arena.root)
+         .field("forest", &self.flow_arena.forest)
          .field("node_map", &self.flow_arena.node_map)
+         .field("dormant", &self.flow_arena.dormant.keys().collect::<Vec<_>>())
          .field("id_factory", &self.id_factory)
+         .field("transitions", &self.transitions)
+         .field("trash", &self.trash)
+         .field("archive", &self.archive)
          .finish()
     }
 }
 
 impl Vessel {
+    /// marks id as edited for the derived indexes and the journal.
+    fn touch(&mut self, id: &EntityId) {
+        self.search.touch(id);
+        self.links.touch(id);
+        self.changes.touch(id);
+    }
     pub fn entity_grow(&mut self) -> EntityId {
-        let entity = Entity::new_time(&self.id_factory);
+        let entity = Entity::new_time(&mut self.id_factory);
         self.entity_insert(entity)
     }
     pub fn entity_insert(&mut self, entity: Entity) -> EntityId {
         let id = entity.id().clone();
         self.flow_arena.grow(Node::from_id(id.clone(), entity)).ok();
+        self.touch(&id);
         id
     }
+    /// pages in on first access.
     pub fn entity_get(&mut self, id: &EntityId) -> Option<&Entity> {
+        self.flow_arena.materialize(id).ok();
         self.flow_arena.node_map.get(id).map(|x| &x.entity)
     }
+    /// pages in on first access.
     pub fn entity_get_mut(&mut self, id: &EntityId) -> Option<&mut Entity> {
+        self.flow_arena.materialize(id).ok();
+        self.touch(id);
         self.flow_arena.node_map.get_mut(id).map(|x| &mut x.entity)
     }
-    pub fn entity_list(&self, id: &EntityId) -> Vec<&Entity> {
+    /// pages in the children of id on first access.
+    pub fn entity_list(&mut self, id: &EntityId) -> Vec<&Entity> {
+        self.flow_arena.page_in(id).ok();
         let vec = self.flow_arena.node_map.get(id).map(|x| x.children.clone()).unwrap_or_default();
         
-        vec.into_iter().filter_map(|id| self.flow_arena.node_map.get(&id)).map(|x| &x.entity).collect()
+        let node_map = &self.flow_arena.node_map;
+        vec.into_iter().filter_map(|id| node_map.get(&id)).map(|x| &x.entity).collect()
+    }
+    /// the children of id, paging them in; empty if id doesn't exist.
+    pub fn entity_children(&mut self, id: &EntityId) -> Vec<EntityId> {
+        self.flow_arena.materialize(id).ok();
+        self.flow_arena.page_in(id).ok();
+        self.flow_arena.node_map.get(id).map(|x| x.children.clone()).unwrap_or_default()
+    }
+    /// the children of root, then the root of each named tree.
+    pub fn entity_top(&mut self) -> Vec<EntityId> {
+        let root = self.flow_arena.root.clone();
+        let mut res = self.entity_children(&root);
+        res.extend(self.flow_arena.forest.iter().map(|tree| tree.id.clone()));
+        res
+    }
+    /// resident entities in tree order: below root first, then each named tree; root excluded.
+    pub fn entity_preorder(&self) -> Vec<EntityId> {
+        let node_map = &self.flow_arena.node_map;
+        let mut res = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut stack: Vec<&EntityId> = self.flow_arena.forest.iter().rev().map(|tree| &tree.id).collect();
+        stack.extend(node_map.get(&self.flow_arena.root).into_iter().flat_map(|node| node.children.iter().rev()));
+        while let Some(id) = stack.pop() {
+            if let Some(node) = node_map.get(id) {
+                if !seen.insert(id) { continue }
+                res.push(id.clone());
+                stack.extend(node.children.iter().rev());
+            }
+        }
+        res
+    }
+    /// id and everything below it, depth first; pages in the subtree.
+    pub fn entity_subtree(&mut self, id: &EntityId) -> Vec<EntityId> {
+        self.flow_arena.materialize(id).ok();
+        let mut res = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let mut stack = vec![id.clone()];
+        while let Some(id) = stack.pop() {
+            self.flow_arena.page_in(&id).ok();
+            if let Some(node) = self.flow_arena.node_map.get(&id) {
+                if !seen.insert(id.clone()) { continue }
+                stack.extend(node.children.iter().rev().cloned());
+                res.push(id);
+            }
+        }
+        res
     }
     pub fn entity_ensure(&mut self, id: &EntityId) -> &mut Entity {
         // match self.flow_arena.node_map.get_mut(id).map(|x| &mut x.entity) {
         //     Some(x) => x,
         //     None => {
-        //         let entity = Entity::new_time(&self.id_factory);
+        //         let entity = Entity::new_time(&mut self.id_factory);
         //         self.flow_arena.node_map.insert(entity.id().clone(), Node::from_id(entity.id().clone().clone(), entity.clone()));
         //         &mut self.flow_arena.node_map.get_mut(&entity.id().clone()).unwrap().entity
         //     }
         // }
-        if !self.flow_arena.node_map.contains_key(id) {
+        if !self.flow_arena.materialize(id).unwrap_or(false) {
             self.entity_insert(Entity::new_id(id));
         }
         self.entity_get_mut(id).expect("contains key")
     }
+    /// the parent of id; None at the top level, i.e. below root or for the root of a named tree.
+    pub fn entity_parent(&mut self, id: &EntityId) -> Option<EntityId> {
+        self.flow_arena.materialize(id).ok();
+        let parent = self.flow_arena.node_map.get(id).and_then(|node| node.parent.clone());
+        parent.filter(|parent| *parent != self.flow_arena.root)
+    }
+    /// moves the subtree of obj to the end of des, or below root if None;
+    /// err if obj is a top-level root, or des is missing or lies in the subtree of obj.
+    pub fn entity_move(&mut self, obj: &EntityId, des: Option<&EntityId>) -> Result<(), ForestError> {
+        self.entity_move_at(obj, des, usize::MAX)
+    }
+    /// as entity_move, but to the nth child of des, counted without obj; past the end appends.
+    pub fn entity_move_at(&mut self, obj: &EntityId, des: Option<&EntityId>, nth: usize) -> Result<(), ForestError> {
+        let des = des.cloned().unwrap_or_else(|| self.flow_arena.root.clone());
+        self.flow_arena.materialize(obj).ok();
+        let len = self.entity_children(&des).iter().filter(|x| *x != obj).count();
+        self.flow_arena.relocate(obj, &des, nth.min(len))?;
+        self.touch(obj);
+        Ok(())
+    }
+    /// removes id; its children move up to its parent.
     pub fn entity_decay(&mut self, id: &EntityId) {
+        self.flow_arena.page_in(id).ok();
+        let parent = self.flow_arena.node_map.get(id).and_then(|node| node.parent.clone());
         self.flow_arena.decay(id).ok();
+        self.touch(id);
+        // Note: decay shifts the places of the children of the parent, too.
+        let siblings = parent.and_then(|parent| self.flow_arena.node_map.get(&parent)).map(|node| node.children.clone());
+        for sibling in siblings.unwrap_or_default().iter() {
+            self.touch(sibling);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolveError {
+    Invalid(ParseIdError),
+    NotFound(String),
+    /// the prefix and every id it matches.
+    Ambiguous(String, Vec<EntityId>),
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::Invalid(e) => write!(f, "{}", e),
+            ResolveError::NotFound(prefix) => write!(f, "no entity matches '{}'", prefix),
+            ResolveError::Ambiguous(prefix, ids) => {
+                write!(f, "'{}' is ambiguous; candidates:", prefix)?;
+                ids.iter().try_for_each(|id| write!(f, " {}", id))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl Vessel {
+    pub fn id_mode(&self) -> IdMode {
+        self.id_factory.mode()
+    }
+    pub fn set_id_mode(&mut self, mode: IdMode) {
+        self.changes.settings = true;
+        self.id_factory.set_mode(mode)
+    }
+    /// an entity by its full text form, or by a unique hex prefix of it like git short hashes.
+    pub fn resolve_prefix(&self, text: &str) -> Result<EntityId, ResolveError> {
+        // Note: dormant ids are read off the chunks, without paging them in.
+        let dormant = self.flow_arena.dormant_ids().unwrap_or_default();
+        let root = &self.flow_arena.root;
+        let ids = self.flow_arena.node_map.keys().filter(|id| *id != root).chain(dormant.iter());
+        resolve_among(ids.collect(), text)
+    }
+}
+
+/// text resolved among ids, as Vessel::resolve_prefix does.
+fn resolve_among(ids: Vec<&EntityId>, text: &str) -> Result<EntityId, ResolveError> {
+    if text.contains('@') {
+        let id: EntityId = text.parse().map_err(ResolveError::Invalid)?;
+        return if ids.contains(&&id) {
+            Ok(id)
+        } else {
+            Err(ResolveError::NotFound(text.to_string()))
+        };
+    }
+    let index = identity::PrefixIndex::new(ids.into_iter());
+    let mut ids = index.find(text);
+    match ids.len() {
+        0 => Err(ResolveError::NotFound(text.to_string())),
+        1 => Ok(ids.pop().expect("one").clone()),
+        _ => Err(ResolveError::Ambiguous(text.to_string(), ids.into_iter().cloned().collect())),
+    }
+}
+
+impl Vessel {
+    /// keeps every subtree below depth encoded until it's accessed; 
+    /// top-level roots are at depth 0.
+    pub fn page_out_below(&mut self, depth: usize) -> Result<(), CompactError> {
+        self.flow_arena.page_out_below(depth)
+    }
+    pub fn page_out(&mut self, id: &EntityId) -> Result<(), CompactError> {
+        self.flow_arena.page_out(id)
+    }
+    pub fn page_in_all(&mut self) -> Result<(), CompactError> {
+        self.flow_arena.page_in_all()
+    }
+    /// runs f with every dormant chunk paged in, then pages the same stubs out again,
+    /// so that a query over the whole vessel doesn't leave it all resident.
+    pub(crate) fn scan<T>(&mut self, f: impl FnOnce(&mut Vessel) -> T) -> T {
+        let stubs: Vec<EntityId> = self.flow_arena.dormant.keys().cloned().collect();
+        self.flow_arena.page_in_all().ok();
+        let res = f(self);
+        for stub in stubs.iter() {
+            self.flow_arena.page_out(stub).ok();
+        }
+        res
+    }
+}
+
+impl Vessel {
+    /// plants a new top-level tree whose root entity is faced with name; None if name is taken.
+    pub fn root_plant(&mut self, name: &str) -> Option<EntityId> {
+        if self.root_named(name).is_some() { return None }
+        let mut entity = Entity::new_time(&mut self.id_factory);
+        entity.face = name.to_string();
+        let id = entity.id().clone();
+        self.flow_arena.plant(name, Node::from_id(id.clone(), entity)).ok()?;
+        self.touch(&id);
+        Some(id)
+    }
+    pub fn root_named(&self, name: &str) -> Option<&EntityId> {
+        self.flow_arena.root_named(name)
+    }
+    /// the named top-level trees, in order.
+    pub fn root_list(&self) -> Vec<(&str, &EntityId)> {
+        self.flow_arena.roots().collect()
+    }
+    /// moves the subtree of obj under the top-level root des.
+    pub fn entity_transplant(&mut self, obj: &EntityId, des: &EntityId) -> Result<(), ForestError> {
+        self.flow_arena.transplant(obj, des)?;
+        self.touch(obj);
+        Ok(())
     }
 }
 
@@ -111,7 +372,9 @@ mod tests {
         vessel.flow_arena.devote_push(&id4, &id1).ok();
         // 0_0 --> id --> [id1, id2, id3]
         //          `-------`-> id4
-        vessel.entity_get_mut(&id).map(|entity| entity.face = format!("Aloha!"));
+        if let Some(entity) = vessel.entity_get_mut(&id) {
+            entity.face = "Aloha!".to_string();
+        }
         println!("{:#?}", vessel);
         println!("{:#?}", vessel.entity_get(&id));
         println!("{:#?}", vessel.entity_list(&id));
@@ -131,9 +394,85 @@ mod tests {
         vessel.flow_arena.devote_push(&id4, &id1).ok();
         // 0_0 --> id --> [id1, id2, id3]
         //          `-------`-> id4
-        vessel.entity_get_mut(&id).map(|entity| entity.face = format!("Aloha!"));
-        vessel.entity_get_mut(&id1).map(|entity| entity.face = format!("Bobi."));
+        if let Some(entity) = vessel.entity_get_mut(&id) {
+            entity.face = "Aloha!".to_string();
+        }
+        if let Some(entity) = vessel.entity_get_mut(&id1) {
+            entity.face = "Bobi.".to_string();
+        }
         vessel.entity_decay(&id);
         println!("{:#?}", vessel);
     }
+    #[test]
+    fn entity_move_at() {
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        let id1 = vessel.entity_grow();
+        let id2 = vessel.entity_grow();
+        vessel.entity_move(&id1, Some(&id)).unwrap();
+        vessel.entity_move_at(&id2, Some(&id), 0).unwrap();
+        assert_eq!(vessel.entity_children(&id), vec![id2.clone(), id1.clone()]);
+        assert_eq!(vessel.entity_parent(&id1), Some(id.clone()));
+        assert_eq!(vessel.entity_parent(&id), None);
+        // past the end appends; never into its own subtree
+        vessel.entity_move_at(&id2, Some(&id), 7).unwrap();
+        assert_eq!(vessel.entity_children(&id), vec![id1.clone(), id2.clone()]);
+        assert_eq!(vessel.entity_move_at(&id, Some(&id1), 0), Err(ForestError::Cycle));
+    }
+    #[test]
+    fn lazy() {
+        use flow_arena::Compact;
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        let id1 = vessel.entity_grow();
+        let id2 = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&id1, &id).ok();
+        vessel.flow_arena.devote_push(&id2, &id1).ok();
+        if let Some(entity) = vessel.entity_get_mut(&id2) {
+            entity.face = "Deep.".to_string();
+        }
+        vessel.page_out_below(1).unwrap();
+        let mut vessel = Vessel::from_compact(&vessel.to_compact()).unwrap();
+        assert!(vessel.flow_arena.node(&id1).is_none());
+        assert_eq!(vessel.entity_list(&id).len(), 1);
+        assert_eq!(vessel.entity_get(&id2).map(|entity| entity.face.as_str()), Some("Deep."));
+        println!("{:#?}", vessel);
+    }
+    #[test]
+    fn resolve_prefix() {
+        use std::time::UNIX_EPOCH;
+        let mut vessel = Vessel::new();
+        let ids: Vec<EntityId> = [0xabc1, 0xabc2, 0x1f].iter().map(|u| {
+            vessel.entity_insert(Entity::new_id(&EntityId::from_parts(UNIX_EPOCH, *u)))
+        }).collect();
+        assert_eq!(vessel.resolve_prefix("abc1"), Ok(ids[0].clone()));
+        assert_eq!(vessel.resolve_prefix(&ids[2].to_string()), Ok(ids[2].clone()));
+        assert_eq!(vessel.resolve_prefix("abc"), Err(ResolveError::Ambiguous("abc".to_string(), ids[..2].to_vec())));
+        assert_eq!(vessel.resolve_prefix("ffff"), Err(ResolveError::NotFound("ffff".to_string())));
+        assert!(matches!(vessel.resolve_prefix("x@y"), Err(ResolveError::Invalid(_))));
+        // root is no entity
+        assert!(vessel.resolve_prefix("0").is_err());
+        // paged out entities resolve, and stay paged out
+        vessel.entity_move(&ids[0], Some(&ids[2])).unwrap();
+        vessel.page_out(&ids[2]).unwrap();
+        assert_eq!(vessel.resolve_prefix("abc1"), Ok(ids[0].clone()));
+        assert!(vessel.flow_arena.is_dormant(&ids[2]));
+    }
+    #[test]
+    fn root_plant() {
+        let mut vessel = Vessel::new();
+        let work = vessel.root_plant("work").unwrap();
+        let home = vessel.root_plant("home").unwrap();
+        // a taken name changes nothing, not even the id factory
+        vessel.id_factory.set_mode(IdMode::Monotonic);
+        let (touched, last) = (vessel.changes.touched.len(), format!("{:?}", vessel.id_factory));
+        assert_eq!(vessel.root_plant("work"), None);
+        assert_eq!((vessel.changes.touched.len(), format!("{:?}", vessel.id_factory)), (touched, last));
+        let id = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&id, &work).ok();
+        vessel.entity_transplant(&id, &home).unwrap();
+        assert_eq!(vessel.flow_arena.root_of(&id), Some(&home));
+        assert_eq!(vessel.root_list(), vec![("work", &work), ("home", &home)]);
+        println!("{:#?}", vessel);
+    }
 }
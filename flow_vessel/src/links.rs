@@ -0,0 +1,291 @@
+//! links.rs contains cross-references between entities, written in bubble as:
+//! 1. `[[abcde]]`: an id, as in the Debug form of EntityId; any hex prefix works, see PrefixIndex.
+//! 2. `[[face text]]`: the entity faced so, ignoring case and surrounding spaces.
+//!
+//! the backlink index is kept like the search index: edits mark entities dirty,
+//! and queries refresh first. a face change re-resolves every link, since it can retarget any.
+
+use std::{collections::{HashMap, HashSet}, ops::Range};
+
+use super::Vessel;
+use super::identity::{EntityId, PrefixIndex};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkTarget {
+    Resolved(EntityId),
+    /// nothing matches, e.g. after the target is deleted.
+    Broken,
+    /// more than one entity matches.
+    Ambiguous(Vec<EntityId>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    /// the text between the brackets.
+    pub text: String,
+    /// byte range of the whole `[[..]]` in bubble.
+    pub span: Range<usize>,
+    pub target: LinkTarget,
+}
+
+/// every `[[..]]` in text as (inner text, span); empty or multi-line ones are skipped.
+pub fn parse_links(text: &str) -> Vec<(String, Range<usize>)> {
+    let mut res = Vec::new();
+    let mut from = 0;
+    while let Some(open) = text[from..].find("[[").map(|i| from + i) {
+        let inner = open + 2;
+        let close = match text[inner..].find("]]") {
+            Some(i) => inner + i,
+            None => break,
+        };
+        let body = &text[inner..close];
+        // Note: `[[a [[b]]` links b.
+        if let Some(i) = body.rfind("[[") {
+            from = inner + i;
+            continue
+        }
+        if !body.trim().is_empty() && !body.contains('\n') {
+            res.push((body.trim().to_string(), open..close + 2));
+        }
+        from = close + 2;
+    }
+    res
+}
+
+fn face_key(face: &str) -> String {
+    face.trim().to_lowercase()
+}
+
+/// lookups for resolving links, rebuilt from faces on each refresh.
+struct Resolver<'a> {
+    by_face: HashMap<String, Vec<&'a EntityId>>,
+    by_prefix: PrefixIndex<'a>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(faces: &'a HashMap<EntityId, String>) -> Self {
+        let mut by_face: HashMap<String, Vec<&EntityId>> = HashMap::new();
+        for (id, face) in faces.iter() {
+            by_face.entry(face_key(face)).or_default().push(id);
+        }
+        Resolver { by_face, by_prefix: PrefixIndex::new(faces.keys()) }
+    }
+    fn resolve(&self, text: &str) -> LinkTarget {
+        let pick = |mut ids: Vec<EntityId>| match ids.len() {
+            0 => LinkTarget::Broken,
+            1 => LinkTarget::Resolved(ids.pop().expect("one")),
+            _ => LinkTarget::Ambiguous(ids),
+        };
+        let ids: Vec<EntityId> = self.by_prefix.find(text).into_iter().cloned().collect();
+        if !ids.is_empty() { return pick(ids) }
+        let key = face_key(text);
+        pick(self.by_face.get(&key).map(|ids| ids.iter().map(|id| (*id).clone()).collect()).unwrap_or_default())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkIndex {
+    /// the links written in each bubble, unresolved.
+    raw: HashMap<EntityId, Vec<(String, Range<usize>)>>,
+    faces: HashMap<EntityId, String>,
+    resolved: HashMap<EntityId, Vec<Link>>,
+    /// target -> sources.
+    back: HashMap<EntityId, HashSet<EntityId>>,
+    dirty: HashSet<EntityId>,
+    /// the whole index must be rebuilt, e.g. right after loading.
+    stale: bool,
+}
+
+impl Default for LinkIndex {
+    fn default() -> Self {
+        LinkIndex {
+            raw: HashMap::new(),
+            faces: HashMap::new(),
+            resolved: HashMap::new(),
+            back: HashMap::new(),
+            dirty: HashSet::new(),
+            stale: true,
+        }
+    }
+}
+
+impl LinkIndex {
+    /// marks id to be re-read before the next query.
+    pub fn touch(&mut self, id: &EntityId) {
+        if !self.stale {
+            self.dirty.insert(id.clone());
+        }
+    }
+    fn unlink(&mut self, source: &EntityId) {
+        for link in self.resolved.remove(source).into_iter().flatten() {
+            if let LinkTarget::Resolved(target) = link.target {
+                let empty = self.back.get_mut(&target).map(|set| {
+                    set.remove(source);
+                    set.is_empty()
+                });
+                if empty == Some(true) { self.back.remove(&target); }
+            }
+        }
+    }
+    /// resolves the links of sources, or of everything if None.
+    fn resolve(&mut self, sources: Option<Vec<EntityId>>) {
+        let sources = match sources {
+            Some(sources) => sources,
+            None => {
+                self.resolved.clear();
+                self.back.clear();
+                self.raw.keys().cloned().collect()
+            }
+        };
+        let resolver = Resolver::new(&self.faces);
+        let mut resolved = Vec::new();
+        for source in sources.into_iter() {
+            let links: Vec<Link> = self.raw.get(&source).into_iter().flatten().map(|(text, span)| Link {
+                text: text.clone(),
+                span: span.clone(),
+                target: resolver.resolve(text),
+            }).collect();
+            resolved.push((source, links));
+        }
+        drop(resolver);
+        for (source, links) in resolved.into_iter() {
+            self.unlink(&source);
+            for link in links.iter() {
+                if let LinkTarget::Resolved(target) = &link.target {
+                    self.back.entry(target.clone()).or_default().insert(source.clone());
+                }
+            }
+            if !links.is_empty() {
+                self.resolved.insert(source, links);
+            }
+        }
+    }
+}
+
+impl Vessel {
+    fn links_refresh(&mut self) {
+        if self.links.stale {
+            self.links = self.scan(|vessel| {
+                let mut index = LinkIndex { stale: false, ..LinkIndex::default() };
+                for node in vessel.flow_arena.node_map.values() {
+                    if node.id() == &vessel.flow_arena.root { continue }
+                    index.faces.insert(node.id().clone(), node.entity.face.clone());
+                    let raw = parse_links(&node.entity.bubble);
+                    if !raw.is_empty() { index.raw.insert(node.id().clone(), raw); }
+                }
+                index.resolve(None);
+                index
+            });
+            return
+        }
+        if self.links.dirty.is_empty() { return }
+        let dirty: Vec<EntityId> = self.links.dirty.drain().collect();
+        let mut renamed = false;
+        for id in dirty.iter() {
+            // Note: an edited entity may be paged out since.
+            self.flow_arena.materialize(id).ok();
+            let node = self.flow_arena.node_map.get(id).filter(|_| id != &self.flow_arena.root);
+            let face = node.map(|node| node.entity.face.clone());
+            let raw = node.map(|node| parse_links(&node.entity.bubble)).unwrap_or_default();
+            let index = &mut self.links;
+            renamed |= index.faces.get(id) != face.as_ref();
+            match face {
+                Some(face) => { index.faces.insert(id.clone(), face); }
+                None => { index.faces.remove(id); }
+            }
+            if raw.is_empty() { index.raw.remove(id); } else { index.raw.insert(id.clone(), raw); }
+        }
+        self.links.resolve(if renamed { None } else { Some(dirty) });
+    }
+    /// links written in the bubble of id, in order.
+    pub fn links_from(&mut self, id: &EntityId) -> Vec<Link> {
+        self.links_refresh();
+        self.links.resolved.get(id).cloned().unwrap_or_default()
+    }
+    /// entities whose bubble links to id, in tree order.
+    pub fn backlinks_to(&mut self, id: &EntityId) -> Vec<EntityId> {
+        self.links_refresh();
+        let sources = match self.links.back.get(id) {
+            Some(sources) => sources.clone(),
+            None => return Vec::new(),
+        };
+        // Note: only the chunks holding sources are paged in, for their order.
+        for source in sources.iter() {
+            self.flow_arena.materialize(source).ok();
+        }
+        self.entity_preorder().into_iter().filter(|id| sources.contains(id)).collect()
+    }
+    /// links that resolve to nothing or to more than one entity, by source in tree order.
+    pub fn broken_links(&mut self) -> Vec<(EntityId, Link)> {
+        self.links_refresh();
+        let sources: Vec<EntityId> = self.links.resolved.iter()
+            .filter(|(_, links)| links.iter().any(|link| !matches!(link.target, LinkTarget::Resolved(_))))
+            .map(|(source, _)| source.clone())
+            .collect();
+        for source in sources.iter() {
+            self.flow_arena.materialize(source).ok();
+        }
+        let resolved = &self.links.resolved;
+        self.entity_preorder().into_iter().flat_map(|id| {
+            let links = resolved.get(&id).cloned().unwrap_or_default();
+            links.into_iter()
+                .filter(|link| !matches!(link.target, LinkTarget::Resolved(_)))
+                .map(move |link| (id.clone(), link))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let text = "see [[abcde]] and [[ Weekly review ]], not [[]] or [[a [[b]] or [[half";
+        let links: Vec<String> = parse_links(text).into_iter().map(|(text, _)| text).collect();
+        assert_eq!(links, vec!["abcde", "Weekly review", "b"]);
+        assert_eq!(parse_links("x[[y]]")[0].1, 1..6);
+    }
+
+    #[test]
+    fn backlinks() {
+        let mut vessel = Vessel::new();
+        let review = vessel.entity_grow();
+        let note = vessel.entity_grow();
+        let other = vessel.entity_grow();
+        vessel.entity_get_mut(&review).unwrap().face = "Weekly Review".to_string();
+        let hex = format!("{:?}", other).trim_matches(|c| c == '[' || c == ']').to_string();
+        vessel.entity_get_mut(&note).unwrap().bubble = format!("before [[weekly review]], then [[{}]].", hex);
+        let targets: Vec<LinkTarget> = vessel.links_from(&note).into_iter().map(|link| link.target).collect();
+        assert_eq!(targets, vec![LinkTarget::Resolved(review.clone()), LinkTarget::Resolved(other.clone())]);
+        assert_eq!(vessel.backlinks_to(&review), vec![note.clone()]);
+        assert!(vessel.broken_links().is_empty());
+
+        // renaming the target retargets the link
+        vessel.entity_get_mut(&review).unwrap().face = "Monthly review".to_string();
+        assert!(vessel.backlinks_to(&review).is_empty());
+        vessel.entity_get_mut(&other).unwrap().face = "weekly review".to_string();
+        assert_eq!(vessel.backlinks_to(&other), vec![note.clone()]);
+
+        // deleting the target breaks it
+        vessel.entity_decay(&other);
+        let broken: Vec<String> = vessel.broken_links().into_iter().map(|(_, link)| link.text).collect();
+        assert_eq!(broken, vec!["weekly review".to_string(), hex]);
+
+        // paged out entities are indexed, and only sources are paged back in
+        let folder = vessel.entity_grow();
+        let quiet = vessel.entity_grow();
+        vessel.entity_move(&note, Some(&folder)).unwrap();
+        vessel.entity_move(&quiet, Some(&folder)).unwrap();
+        let shelf = vessel.entity_grow();
+        vessel.entity_move(&review, Some(&shelf)).unwrap();
+        vessel.entity_get_mut(&review).unwrap().face = "Weekly Review".to_string();
+        vessel.page_out(&folder).unwrap();
+        vessel.page_out(&shelf).unwrap();
+        vessel.links = LinkIndex::default();
+        assert_eq!(vessel.links_from(&note)[0].target, LinkTarget::Resolved(review.clone()));
+        assert!(vessel.flow_arena.is_dormant(&folder) && vessel.flow_arena.is_dormant(&shelf));
+        assert_eq!(vessel.backlinks_to(&review), vec![note.clone()]);
+        assert!(!vessel.flow_arena.is_dormant(&folder) && vessel.flow_arena.is_dormant(&shelf));
+    }
+}
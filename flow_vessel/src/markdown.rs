@@ -0,0 +1,345 @@
+//! markdown.rs contains the markdown outline export, for pasting task trees into chats and docs:
+//! ```text
+//! - [ ] Write report #work/report
+//!
+//!   notes from the bubble, indented under the item.
+//!
+//!   - [x] Collect numbers
+//! ```
+//! Done entities are checked; everything else is unchecked.
+//!
+//! the import reads such lists back, or plain indented text where every line is an item:
+//! 1. `-`, `*`, `+`, `1.` and `1)` markers; tabs count as 4 spaces;
+//! 2. `[ ]` / `[x]` checkboxes, and the trailing `(Status)`, `#tags` and `` `id` `` written by the export;
+//! 3. other lines below an item become its bubble; text before the first item is dropped.
+
+use std::fmt;
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::tag::Tag;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    /// levels below the exported entities to include; None for all.
+    pub max_depth: Option<usize>,
+    /// appends the status, e.g. `(Marching)`, to every item that isn't New or Done.
+    pub status: bool,
+    /// appends the id as a code span, e.g. `` `000000000000001f@1614556800.000000000` ``.
+    pub ids: bool,
+}
+
+const INDENT: &str = "  ";
+
+impl Entity {
+    /// the list item line, without indent and newline.
+    fn markdown_item(&self, options: &MarkdownOptions) -> String {
+        let check = if self.process == ProcessStatus::Done { "x" } else { " " };
+        let mut line = format!("- [{}]", check);
+        let face = self.face.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !face.is_empty() {
+            line.push(' ');
+            line.push_str(&face);
+        }
+        if options.status && !matches!(self.process, ProcessStatus::New | ProcessStatus::Done) {
+            line.push_str(&format!(" ({})", self.process.type_str()));
+        }
+        for tag in self.tags.into_iter() {
+            // Note: a space would end the tag.
+            line.push_str(&format!(" #{}", tag.replace(char::is_whitespace, "-")));
+        }
+        if options.ids {
+            line.push_str(&format!(" `{}`", self.id()));
+        }
+        line
+    }
+}
+
+impl Vessel {
+    /// id and its subtree as a nested markdown list; the whole vessel if id is None.
+    pub fn export_markdown(&mut self, id: Option<&EntityId>, options: &MarkdownOptions) -> String {
+        let top = match id {
+            Some(id) => vec![id.clone()],
+            None => self.entity_top(),
+        };
+        let mut out = String::new();
+        for id in top.iter() {
+            self.markdown_write(id, 0, options, &mut out);
+        }
+        out
+    }
+    fn markdown_write(&mut self, id: &EntityId, depth: usize, options: &MarkdownOptions, out: &mut String) {
+        let entity = match self.entity_get(id) {
+            Some(entity) => entity,
+            None => return,
+        };
+        let indent = INDENT.repeat(depth);
+        out.push_str(&indent);
+        out.push_str(&entity.markdown_item(options));
+        out.push('\n');
+        let bubble = entity.bubble.trim_end();
+        if !bubble.trim().is_empty() {
+            out.push('\n');
+            for line in bubble.lines() {
+                if !line.trim().is_empty() {
+                    out.push_str(&indent);
+                    out.push_str(INDENT);
+                    out.push_str(line);
+                }
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        if options.max_depth.is_some_and(|max| depth >= max) { return }
+        for child in self.entity_children(id) {
+            self.markdown_write(&child, depth + 1, options, out);
+        }
+    }
+}
+
+/// one entity of an outline import, in tree order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutlineItem {
+    /// 0 for the items put right under the chosen node.
+    pub depth: usize,
+    pub face: String,
+    pub bubble: String,
+    pub process: ProcessStatus,
+    pub tags: Vec<Tag>,
+    /// the created entity; None in a dry run.
+    pub id: Option<EntityId>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutlineError {
+    /// the node to import under doesn't exist.
+    Missing,
+    /// the text can't be read as the format asked for.
+    Malformed(String),
+}
+
+impl fmt::Display for OutlineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutlineError::Missing => write!(f, "no such entity to import under"),
+            OutlineError::Malformed(e) => write!(f, "malformed outline: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OutlineError {}
+
+/// leading whitespace width and the rest of line.
+fn indent_of(line: &str) -> (usize, &str) {
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4,
+            _ => return (width, &line[i..]),
+        }
+    }
+    (width, "")
+}
+
+/// the text after a list marker, if line starts with one.
+fn strip_marker(text: &str) -> Option<&str> {
+    let rest = match text.chars().next()? {
+        '-' | '*' | '+' => &text[1..],
+        _ => {
+            let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 { return None }
+            text[digits..].strip_prefix('.').or_else(|| text[digits..].strip_prefix(')'))?
+        }
+    };
+    if rest.is_empty() { return Some(rest) }
+    rest.strip_prefix(char::is_whitespace).map(str::trim_start)
+}
+
+/// an item line, with the marker and indent already stripped.
+fn outline_item(depth: usize, text: &str) -> OutlineItem {
+    let mut item = OutlineItem {
+        depth,
+        face: String::new(),
+        bubble: String::new(),
+        process: ProcessStatus::New,
+        tags: Vec::new(),
+        id: None,
+    };
+    let mut text = text.trim();
+    for (check, process) in [("[ ]", ProcessStatus::New), ("[x]", ProcessStatus::Done), ("[X]", ProcessStatus::Done)].iter() {
+        if let Some(rest) = text.strip_prefix(check) {
+            text = rest.trim_start();
+            item.process = *process;
+        }
+    }
+    let mut words: Vec<&str> = text.split_whitespace().collect();
+    if words.last().is_some_and(|word| word.len() > 2 && word.starts_with('`') && word.ends_with('`')
+        && word.trim_matches('`').parse::<EntityId>().is_ok()) {
+        words.pop();
+    }
+    while let Some(tag) = words.last().and_then(|word| word.strip_prefix('#')).filter(|tag| !tag.is_empty()) {
+        item.tags.insert(0, tag.to_string());
+        words.pop();
+    }
+    if let Some(status) = words.last().and_then(|word| word.strip_prefix('(')).and_then(|word| word.strip_suffix(')')) {
+        let process = ProcessStatus::reflect(status);
+        if process.type_str() == status && item.process != ProcessStatus::Done {
+            item.process = process;
+            words.pop();
+        }
+    }
+    item.face = words.join(" ");
+    item
+}
+
+/// the items of an outline in tree order, with their depth.
+pub fn parse_outline(text: &str) -> Vec<OutlineItem> {
+    let listed = text.lines().any(|line| strip_marker(indent_of(line).1).is_some());
+    let mut items: Vec<OutlineItem> = Vec::new();
+    // indent of each open ancestor
+    let mut stack: Vec<usize> = Vec::new();
+    // bubble lines of the last item, with their indent
+    let mut bubble: Vec<(usize, &str)> = Vec::new();
+    let flush = |items: &mut Vec<OutlineItem>, bubble: &mut Vec<(usize, &str)>| {
+        let min = bubble.iter().filter(|(_, rest)| !rest.is_empty()).map(|(indent, _)| *indent).min().unwrap_or(0);
+        let lines: Vec<String> = bubble.drain(..).map(|(indent, rest)| {
+            if rest.is_empty() { String::new() } else { format!("{}{}", " ".repeat(indent - min), rest) }
+        }).collect();
+        if let Some(item) = items.last_mut() {
+            item.bubble = lines.join("\n").trim_matches('\n').to_string();
+        }
+    };
+    for line in text.lines() {
+        let (indent, rest) = indent_of(line.trim_end());
+        let body = if listed { strip_marker(rest) } else if rest.is_empty() { None } else { Some(rest) };
+        match body {
+            Some(body) => {
+                flush(&mut items, &mut bubble);
+                while stack.last().is_some_and(|top| *top >= indent) {
+                    stack.pop();
+                }
+                items.push(outline_item(stack.len(), body));
+                stack.push(indent);
+            }
+            None => if !items.is_empty() { bubble.push((indent, rest)) }
+        }
+    }
+    flush(&mut items, &mut bubble);
+    items
+}
+
+impl Vessel {
+    /// grows the outline in text as the last children of under, or below root if None.
+    /// a dry run only reports the items it would create.
+    pub fn import_outline(&mut self, under: Option<&EntityId>, text: &str, dry_run: bool) -> Result<Vec<OutlineItem>, OutlineError> {
+        if let Some(under) = under {
+            if self.entity_get(under).is_none() { return Err(OutlineError::Missing) }
+        }
+        let mut items = parse_outline(text);
+        if !dry_run { self.outline_grow(under, &mut items) }
+        Ok(items)
+    }
+    /// creates items as parsed, filling in their ids.
+    pub(crate) fn outline_grow(&mut self, under: Option<&EntityId>, items: &mut [OutlineItem]) {
+        let mut parents: Vec<EntityId> = Vec::new();
+        for item in items.iter_mut() {
+            parents.truncate(item.depth);
+            let mut entity = Entity::new_time(&mut self.id_factory);
+            entity.face = item.face.clone();
+            entity.bubble = item.bubble.clone();
+            entity.process = item.process;
+            for tag in item.tags.iter() {
+                entity.tags.push(tag.clone());
+            }
+            let id = self.entity_insert(entity);
+            self.entity_move(&id, parents.last().or(under)).ok();
+            parents.push(id.clone());
+            item.id = Some(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flow_arena::Flow;
+
+    #[test]
+    fn export() {
+        let mut vessel = Vessel::new();
+        let report = vessel.entity_grow();
+        let entity = vessel.entity_get_mut(&report).unwrap();
+        entity.face = "Write report".to_string();
+        entity.bubble = "first line\n\nsecond  line\n".to_string();
+        entity.tags.push("work/report".to_string());
+        entity.process = ProcessStatus::Marching;
+        let numbers = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&numbers, &report).unwrap();
+        let entity = vessel.entity_get_mut(&numbers).unwrap();
+        entity.face = "Collect numbers".to_string();
+        entity.process = ProcessStatus::Done;
+
+        let plain = vessel.export_markdown(None, &MarkdownOptions::default());
+        assert_eq!(plain, "\
+- [ ] Write report #work/report
+
+  first line
+
+  second  line
+
+  - [x] Collect numbers
+");
+        let options = MarkdownOptions { max_depth: Some(0), status: true, ids: true };
+        let short = vessel.export_markdown(Some(&report), &options);
+        assert!(short.starts_with(&format!("- [ ] Write report (Marching) #work/report `{}`\n", report)));
+        assert!(!short.contains("Collect"));
+    }
+
+    #[test]
+    fn parse() {
+        let text = "\
+intro, dropped
+- [ ] Write report #work/report
+  first line
+
+      indented
+\t* [x] Collect numbers #data
+  1. Plain (Pending)
++ Next `000000000000001f@1614556800.000000000`
+";
+        let items: Vec<_> = parse_outline(text).into_iter()
+            .map(|item| (item.depth, item.face, item.process, item.tags, item.bubble))
+            .collect();
+        assert_eq!(items, vec![
+            (0, "Write report".to_string(), ProcessStatus::New, vec!["work/report".to_string()], "first line\n\n    indented".to_string()),
+            (1, "Collect numbers".to_string(), ProcessStatus::Done, vec!["data".to_string()], String::new()),
+            (1, "Plain".to_string(), ProcessStatus::Pending, vec![], String::new()),
+            (0, "Next".to_string(), ProcessStatus::New, vec![], String::new()),
+        ]);
+        let plain: Vec<_> = parse_outline("a\n  b\n    c\n  d\n\n").into_iter().map(|item| (item.depth, item.face)).collect();
+        assert_eq!(plain, vec![(0, "a".into()), (1, "b".into()), (2, "c".into()), (1, "d".into())]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut vessel = Vessel::new();
+        let home = vessel.entity_grow();
+        let text = "- [ ] Write report #work\n\n  notes\n\n  - [x] Collect numbers\n- [ ] Review (Marching)\n";
+        let dry = vessel.import_outline(Some(&home), text, true).unwrap();
+        assert!(dry.iter().all(|item| item.id.is_none()));
+        assert!(vessel.entity_children(&home).is_empty());
+
+        let items = vessel.import_outline(Some(&home), text, false).unwrap();
+        let ids: Vec<EntityId> = items.iter().map(|item| item.id.clone().unwrap()).collect();
+        assert_eq!(vessel.entity_children(&home), vec![ids[0].clone(), ids[2].clone()]);
+        assert_eq!(vessel.entity_children(&ids[0]), vec![ids[1].clone()]);
+        let options = MarkdownOptions { status: true, ..MarkdownOptions::default() };
+        let exported: String = ids.iter().filter(|id| *id != &ids[1])
+            .map(|id| vessel.export_markdown(Some(id), &options))
+            .collect();
+        assert_eq!(exported, text);
+        assert_eq!(vessel.import_outline(Some(&EntityId::from_parts(std::time::UNIX_EPOCH, 7)), text, true), Err(OutlineError::Missing));
+    }
+}
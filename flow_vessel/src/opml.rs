@@ -0,0 +1,171 @@
+//! opml.rs contains OPML import and export, for moving outlines to and from other outliners:
+//! ```text
+//! <outline text="face" _note="bubble" _status="Marching" _tags="work/report,urgent">
+//! ```
+//! `_status` and `_tags` are our own; `_complete="true"` from other outliners also reads as Done.
+//! unknown elements and attributes are ignored.
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::ProcessStatus;
+use super::markdown::{OutlineItem, OutlineError};
+
+/// escapes text for a double-quoted attribute; line breaks and tabs survive as character references.
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\n' => res.push_str("&#10;"),
+            '\r' => res.push_str("&#13;"),
+            '\t' => res.push_str("&#9;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+impl Vessel {
+    /// id and its subtree as an OPML document; the whole vessel if id is None.
+    pub fn export_opml(&mut self, id: Option<&EntityId>, title: &str) -> String {
+        let top = match id {
+            Some(id) => vec![id.clone()],
+            None => self.entity_top(),
+        };
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<opml version=\"2.0\">\n");
+        out.push_str(&format!("  <head>\n    <title>{}</title>\n  </head>\n", escape(title)));
+        out.push_str("  <body>\n");
+        for id in top.iter() {
+            self.opml_write(id, 2, &mut out);
+        }
+        out.push_str("  </body>\n</opml>\n");
+        out
+    }
+    fn opml_write(&mut self, id: &EntityId, depth: usize, out: &mut String) {
+        let entity = match self.entity_get(id) {
+            Some(entity) => entity,
+            None => return,
+        };
+        let indent = "  ".repeat(depth);
+        out.push_str(&format!("{}<outline text=\"{}\"", indent, escape(&entity.face)));
+        if !entity.bubble.is_empty() {
+            out.push_str(&format!(" _note=\"{}\"", escape(&entity.bubble)));
+        }
+        if entity.process != ProcessStatus::New {
+            out.push_str(&format!(" _status=\"{}\"", entity.process.type_str()));
+        }
+        let tags: Vec<String> = entity.tags.into_iter().collect();
+        if !tags.is_empty() {
+            out.push_str(&format!(" _tags=\"{}\"", escape(&tags.join(","))));
+        }
+        let children = self.entity_children(id);
+        if children.is_empty() {
+            out.push_str("/>\n");
+            return
+        }
+        out.push_str(">\n");
+        for child in children.iter() {
+            self.opml_write(child, depth + 1, out);
+        }
+        out.push_str(&format!("{}</outline>\n", indent));
+    }
+    /// grows the outlines in an OPML document as the last children of under, or below root if None.
+    /// a dry run only reports the items it would create.
+    pub fn import_opml(&mut self, under: Option<&EntityId>, xml: &str, dry_run: bool) -> Result<Vec<OutlineItem>, OutlineError> {
+        if let Some(under) = under {
+            if self.entity_get(under).is_none() { return Err(OutlineError::Missing) }
+        }
+        let mut items = parse_opml(xml)?;
+        if !dry_run { self.outline_grow(under, &mut items) }
+        Ok(items)
+    }
+}
+
+/// the outlines in the body of an OPML document, in tree order.
+pub fn parse_opml(xml: &str) -> Result<Vec<OutlineItem>, OutlineError> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| OutlineError::Malformed(e.to_string()))?;
+    let opml = doc.root_element();
+    if !opml.has_tag_name("opml") {
+        return Err(OutlineError::Malformed(format!("expected <opml>, found <{}>", opml.tag_name().name())));
+    }
+    let body = opml.children().find(|node| node.has_tag_name("body"))
+        .ok_or_else(|| OutlineError::Malformed("no <body>".to_string()))?;
+    let mut items = Vec::new();
+    let mut stack: Vec<_> = body.children().filter(|node| node.has_tag_name("outline")).rev().map(|node| (0, node)).collect();
+    while let Some((depth, node)) = stack.pop() {
+        let mut process = node.attribute("_status").map_or(ProcessStatus::New, ProcessStatus::reflect);
+        if node.attribute("_complete") == Some("true") {
+            process = ProcessStatus::Done;
+        }
+        let tags = node.attribute("_tags").into_iter()
+            .flat_map(|tags| tags.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+        items.push(OutlineItem {
+            depth,
+            face: node.attribute("text").or_else(|| node.attribute("title")).unwrap_or_default().to_string(),
+            bubble: node.attribute("_note").unwrap_or_default().to_string(),
+            process,
+            tags,
+            id: None,
+        });
+        stack.extend(node.children().filter(|node| node.has_tag_name("outline")).rev().map(|node| (depth + 1, node)));
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut vessel = Vessel::new();
+        let report = vessel.entity_grow();
+        let entity = vessel.entity_get_mut(&report).unwrap();
+        entity.face = "Write <report> & \"send\"".to_string();
+        entity.bubble = "first\n\tsecond".to_string();
+        entity.tags.push("work/report".to_string());
+        entity.tags.push("urgent".to_string());
+        entity.process = ProcessStatus::Marching;
+        let xml = vessel.export_opml(Some(&report), "Tasks");
+        assert!(xml.contains("text=\"Write &lt;report&gt; &amp; &quot;send&quot;\""));
+
+        let home = vessel.entity_grow();
+        let items = vessel.import_opml(Some(&home), &xml, false).unwrap();
+        assert_eq!(items.len(), 1);
+        let id = items[0].id.clone().unwrap();
+        assert_eq!(vessel.entity_children(&home), vec![id.clone()]);
+        let (original, copy) = (vessel.entity_get(&report).unwrap().clone(), vessel.entity_get(&id).unwrap().clone());
+        assert_eq!((&copy.face, &copy.bubble, copy.process, &copy.tags), (&original.face, &original.bubble, original.process, &original.tags));
+    }
+
+    #[test]
+    fn foreign() {
+        let xml = r#"<?xml version="1.0"?>
+<opml version="1.0">
+  <head><title>x</title><expansionState>0</expansionState></head>
+  <body>
+    <outline text="a" _complete="true" created="Mon, 01 Mar 2021">
+      <outline title="b" type="link" url="https://example.com"/>
+    </outline>
+    <outline text="c" _status="Unheard"/>
+  </body>
+</opml>"#;
+        let items: Vec<_> = parse_opml(xml).unwrap().into_iter().map(|item| (item.depth, item.face, item.process)).collect();
+        assert_eq!(items, vec![
+            (0, "a".to_string(), ProcessStatus::Done),
+            (1, "b".to_string(), ProcessStatus::New),
+            (0, "c".to_string(), ProcessStatus::New),
+        ]);
+        assert!(matches!(parse_opml("<html/>"), Err(OutlineError::Malformed(_))));
+        assert!(matches!(parse_opml("<opml><body>"), Err(OutlineError::Malformed(_))));
+    }
+}
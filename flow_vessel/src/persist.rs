@@ -0,0 +1,246 @@
+//! persist.rs contains:
+//! 1. (de)serialization of the whole Vessel as a document with a schema version.
+//! 2. save / load to a file; saving writes a sibling temp file and renames it over, loading purges the trash.
+
+use std::{fmt, fs, io::{self, Write}, path::{Path, PathBuf}, time::SystemTime};
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
+use flow_arena::FlowArena;
+
+use super::Vessel;
+use super::identity::{EntityId, EntityIdFactory};
+use super::entity::Entity;
+use super::process::TransitionTable;
+use super::trash::Trash;
+use super::archive::Archive;
+
+/// the schema version written into every document; bumped whenever the document changes shape.
+pub const SCHEMA_VERSION: u32 = 5;
+
+#[derive(Serialize)]
+struct VesselDocRef<'a> {
+    version: u32,
+    flow_arena: &'a FlowArena<EntityId, Entity>,
+    id_factory: &'a EntityIdFactory,
+    transitions: &'a TransitionTable,
+    trash: &'a Trash,
+    archive: &'a Archive,
+}
+
+#[derive(Deserialize)]
+struct VesselDoc {
+    version: u32,
+    flow_arena: FlowArena<EntityId, Entity>,
+    id_factory: EntityIdFactory,
+    /// since version 2.
+    #[serde(default)]
+    transitions: TransitionTable,
+    /// since version 4.
+    #[serde(default)]
+    trash: Trash,
+    /// since version 5.
+    #[serde(default)]
+    archive: Archive,
+}
+
+/// only the version; read ahead so that future documents fail on version, not on shape.
+#[derive(Deserialize)]
+struct VersionDoc {
+    version: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum PersistError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    MissingVersion,
+    FutureVersion { found: u32, supported: u32 },
+    /// a journal line other than the last can't be read; lines count from 1.
+    Corrupt { line: usize },
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PersistError::*;
+        match self {
+            Io(e) => write!(f, "io error: {}", e),
+            Json(e) => write!(f, "malformed vessel document: {}", e),
+            MissingVersion => write!(f, "vessel document has no schema version"),
+            FutureVersion { found, supported } => write!(f,
+                "vessel document has schema version {}, but only up to {} is supported; please upgrade",
+                found, supported
+            ),
+            Corrupt { line } => write!(f, "journal is corrupt at line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PersistError::Io(e) => Some(e),
+            PersistError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PersistError {
+    fn from(e: io::Error) -> Self {
+        PersistError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for PersistError {
+    fn from(e: serde_json::Error) -> Self {
+        PersistError::Json(e)
+    }
+}
+
+fn check_version(version: u32) -> Result<(), PersistError> {
+    if version > SCHEMA_VERSION {
+        Err(PersistError::FutureVersion { found: version, supported: SCHEMA_VERSION })
+    } else {
+        Ok(())
+    }
+}
+
+impl Serialize for Vessel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        VesselDocRef {
+            version: SCHEMA_VERSION,
+            flow_arena: &self.flow_arena,
+            id_factory: &self.id_factory,
+            transitions: &self.transitions,
+            trash: &self.trash,
+            archive: &self.archive,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Vessel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let doc = VesselDoc::deserialize(deserializer)?;
+        check_version(doc.version).map_err(de::Error::custom)?;
+        Ok(Vessel {
+            flow_arena: doc.flow_arena,
+            id_factory: doc.id_factory,
+            transitions: doc.transitions,
+            trash: doc.trash,
+            archive: doc.archive,
+            search: Default::default(),
+            links: Default::default(),
+            changes: Default::default(),
+        })
+    }
+}
+
+impl Vessel {
+    pub fn to_json(&self) -> Result<String, PersistError> {
+        Ok(serde_json::to_string(self)?)
+    }
+    pub fn from_json(json: &str) -> Result<Self, PersistError> {
+        let version: VersionDoc = serde_json::from_str(json)?;
+        check_version(version.version.ok_or(PersistError::MissingVersion)?)?;
+        Ok(serde_json::from_str(json)?)
+    }
+    /// writes to a temp file next to path, flushes it to disk, then renames it over path;
+    /// a crash midway leaves the old file intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), PersistError> {
+        let path = path.as_ref();
+        let json = self.to_json()?;
+        let tmp = temp_path(path);
+        let res = (|| {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, path)
+        })();
+        if res.is_err() {
+            fs::remove_file(&tmp).ok();
+        }
+        res.map_err(PersistError::from)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PersistError> {
+        let json = fs::read_to_string(path)?;
+        let mut vessel = Self::from_json(&json)?;
+        vessel.trash_purge(SystemTime::now());
+        Ok(vessel)
+    }
+}
+
+/// `dir/name` -> `dir/.name.tmp`, on the same file system so that rename is atomic.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flow_arena::Flow;
+
+    fn make_vessel() -> Vessel {
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        let id1 = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&id1, &id).ok();
+        if let Some(entity) = vessel.entity_get_mut(&id1) {
+            entity.face = "Aloha!".to_string();
+        }
+        vessel.id_factory.incr_id();
+        vessel.id_factory.incr_id();
+        vessel
+    }
+
+    #[test]
+    fn json() {
+        let vessel = make_vessel();
+        let json = vessel.to_json().unwrap();
+        println!("{}", json);
+        let _vessel = Vessel::from_json(&json).unwrap();
+        assert_eq!(vessel.flow_arena, _vessel.flow_arena);
+        assert_eq!(format!("{:?}", vessel.id_factory), format!("{:?}", _vessel.id_factory));
+        // version 1 has no transition table
+        let mut value = serde_json::to_value(&vessel).unwrap();
+        value["version"] = 1.into();
+        value.as_object_mut().unwrap().remove("transitions");
+        let _vessel = Vessel::from_json(&value.to_string()).unwrap();
+        assert_eq!(_vessel.transitions, TransitionTable::default());
+    }
+
+    #[test]
+    fn future_version() {
+        let mut value = serde_json::to_value(make_vessel()).unwrap();
+        value["version"] = (SCHEMA_VERSION + 1).into();
+        // unknown shape, too; the version is what gets reported.
+        value["flow_arena"] = "??".into();
+        match Vessel::from_json(&value.to_string()) {
+            Err(PersistError::FutureVersion { found, .. }) => assert_eq!(found, SCHEMA_VERSION + 1),
+            res => panic!("{:?}", res),
+        }
+        value.as_object_mut().unwrap().remove("version");
+        assert!(matches!(Vessel::from_json(&value.to_string()), Err(PersistError::MissingVersion)));
+    }
+
+    #[test]
+    fn save_load() {
+        let dir = std::env::temp_dir().join(format!("flow_vessel-persist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vessel.json");
+        let vessel = make_vessel();
+        vessel.save(&path).unwrap();
+        // saving again replaces the file in place
+        vessel.save(&path).unwrap();
+        assert!(!temp_path(&path).exists());
+        let _vessel = Vessel::load(&path).unwrap();
+        assert_eq!(vessel.flow_arena, _vessel.flow_arena);
+        // the trash is purged on load
+        let mut vessel = vessel;
+        let id = vessel.entity_grow();
+        vessel.entity_trash_at(&id, SystemTime::UNIX_EPOCH);
+        vessel.save(&path).unwrap();
+        assert!(Vessel::load(&path).unwrap().trash_list().is_empty());
+        assert!(matches!(Vessel::load(dir.join("absent.json")), Err(PersistError::Io(_))));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
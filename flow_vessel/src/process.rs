@@ -0,0 +1,283 @@
+//! process.rs contains:
+//! 1. TransitionTable: which ProcessStatus moves are allowed; configurable per vessel.
+//! 2. Transition: one entry of an entity's status history.
+//! 3. queries over the history: completion time, time spent in a status, completed within a span.
+
+use std::{collections::HashSet, fmt, time::{Duration, SystemTime}};
+use chrono::{Datelike, Duration as ChronoDuration, Local, TimeZone};
+use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub from: ProcessStatus,
+    pub to: ProcessStatus,
+    pub time: SystemTime,
+}
+
+impl Compact for Transition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.from.encode(buf);
+        self.to.encode(buf);
+        self.time.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(Transition {
+            from: Compact::decode(src)?,
+            to: Compact::decode(src)?,
+            time: Compact::decode(src)?,
+        })
+    }
+}
+
+/// allowed (from, to) moves; staying put is always allowed and never recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransitionTable {
+    allowed: HashSet<(ProcessStatus, ProcessStatus)>,
+}
+
+impl Default for TransitionTable {
+    /// forward along New -> Planning -> Pending -> Marching -> Done, with skips;
+    /// back from Pending and Marching to planning, and Done may be reopened.
+    fn default() -> Self {
+        use ProcessStatus::*;
+        let mut table = Self::none();
+        let all = ProcessStatus::vec_all();
+        for (i, from) in all.iter().enumerate() {
+            for to in all[i + 1..].iter() {
+                table.allow(*from, *to);
+            }
+        }
+        table.allow(Pending, Planning)
+             .allow(Marching, Planning)
+             .allow(Marching, Pending)
+             .allow(Done, Marching)
+             .allow(Done, Planning);
+        table
+    }
+}
+
+impl TransitionTable {
+    /// forbids every move.
+    pub fn none() -> Self {
+        TransitionTable { allowed: HashSet::new() }
+    }
+    /// allows every move.
+    pub fn all() -> Self {
+        let mut table = Self::none();
+        for from in ProcessStatus::vec_all() {
+            for to in ProcessStatus::vec_all() {
+                table.allow(from, to);
+            }
+        }
+        table
+    }
+    pub fn allow(&mut self, from: ProcessStatus, to: ProcessStatus) -> &mut Self {
+        self.allowed.insert((from, to));
+        self
+    }
+    pub fn forbid(&mut self, from: ProcessStatus, to: ProcessStatus) -> &mut Self {
+        self.allowed.remove(&(from, to));
+        self
+    }
+    pub fn permits(&self, from: ProcessStatus, to: ProcessStatus) -> bool {
+        from == to || self.allowed.contains(&(from, to))
+    }
+    /// the allowed moves in a stable order.
+    fn pairs(&self) -> Vec<(ProcessStatus, ProcessStatus)> {
+        let mut pairs: Vec<_> = self.allowed.iter().cloned().collect();
+        pairs.sort_by_key(|(from, to)| (from.order(), to.order()));
+        pairs
+    }
+}
+
+impl Serialize for TransitionTable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.pairs().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TransitionTable {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs: Vec<(ProcessStatus, ProcessStatus)> = Vec::deserialize(deserializer)?;
+        Ok(TransitionTable { allowed: pairs.into_iter().collect() })
+    }
+}
+
+impl Compact for TransitionTable {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let pairs = self.pairs();
+        (pairs.len() as u64).encode(buf);
+        for (from, to) in pairs.iter() {
+            from.encode(buf);
+            to.encode(buf);
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let len = u64::decode(src)?;
+        let mut table = Self::none();
+        for _ in 0..len {
+            let from = ProcessStatus::decode(src)?;
+            table.allow(from, ProcessStatus::decode(src)?);
+        }
+        Ok(table)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransitError {
+    Missing,
+    Forbidden { from: ProcessStatus, to: ProcessStatus },
+}
+
+impl fmt::Display for TransitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitError::Missing => write!(f, "no such entity"),
+            TransitError::Forbidden { from, to } => write!(f,
+                "moving from {} to {} is not allowed", from.type_str(), to.type_str()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransitError {}
+
+impl Entity {
+    /// when the entity was last marked Done; None unless it's Done now.
+    pub fn completed_at(&self) -> Option<SystemTime> {
+        if self.process != ProcessStatus::Done { return None }
+        self.history.iter().rev().find(|tr| tr.to == ProcessStatus::Done).map(|tr| tr.time)
+    }
+    /// total time spent in status up to now, counting from the creation of the entity.
+    pub fn time_in(&self, status: ProcessStatus, now: SystemTime) -> Duration {
+        let initial = self.history.first().map_or(self.process, |tr| tr.from);
+        let mut current = (initial, self.created());
+        let mut total = Duration::default();
+        let mut add = |(from, since): (ProcessStatus, SystemTime), until: SystemTime| {
+            if from == status {
+                total += until.duration_since(since).unwrap_or_default();
+            }
+        };
+        for tr in self.history.iter() {
+            add(current, tr.time);
+            current = (tr.to, tr.time);
+        }
+        add(current, now);
+        total
+    }
+}
+
+/// the local week holding now, from Monday 00:00 to the next Monday 00:00.
+pub fn week_of(now: SystemTime) -> (SystemTime, SystemTime) {
+    let today = chrono::DateTime::<Local>::from(now).date_naive();
+    let monday = today - ChronoDuration::days(today.weekday().num_days_from_monday() as i64);
+    let midnight = |date: chrono::NaiveDate| Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight"))
+        .earliest().map_or(now, SystemTime::from);
+    (midnight(monday), midnight(monday + ChronoDuration::days(7)))
+}
+
+impl Vessel {
+    pub fn transition_table(&self) -> &TransitionTable {
+        &self.transitions
+    }
+    pub fn transition_table_mut(&mut self) -> &mut TransitionTable {
+        self.changes.settings = true;
+        &mut self.transitions
+    }
+    /// moves id to status now, recording it in the history; checked against the transition table.
+    /// a recurring entity marked Done spawns its next instance.
+    pub fn entity_transit(&mut self, id: &EntityId, to: ProcessStatus) -> Result<(), TransitError> {
+        self.entity_transit_at(id, to, SystemTime::now())
+    }
+    pub fn entity_transit_at(&mut self, id: &EntityId, to: ProcessStatus, time: SystemTime) -> Result<(), TransitError> {
+        let from = self.entity_get(id).ok_or(TransitError::Missing)?.process;
+        if from == to { return Ok(()) }
+        if !self.transitions.permits(from, to) {
+            return Err(TransitError::Forbidden { from, to });
+        }
+        let entity = self.entity_get_mut(id).expect("materialized");
+        entity.process = to;
+        entity.history.push(Transition { from, to, time });
+        self.on_transit(id, to, time);
+        Ok(())
+    }
+    /// entities marked Done within [start, end), in tree order.
+    pub fn completed_within(&mut self, start: SystemTime, end: SystemTime) -> Vec<EntityId> {
+        self.scan(|vessel| {
+            let node_map = &vessel.flow_arena.node_map;
+            vessel.entity_preorder().into_iter().filter(|id| {
+                node_map.get(id)
+                    .and_then(|node| node.entity.completed_at())
+                    .is_some_and(|time| start <= time && time < end)
+            }).collect()
+        })
+    }
+    pub fn completed_this_week(&mut self) -> Vec<EntityId> {
+        let (start, end) = week_of(SystemTime::now());
+        self.completed_within(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ProcessStatus::*;
+    use crate::testing::at;
+
+    #[test]
+    fn table() {
+        let table = TransitionTable::default();
+        assert!(table.permits(New, Marching) && table.permits(Done, Marching));
+        assert!(!table.permits(Done, New) && !table.permits(Planning, New));
+        assert!(TransitionTable::none().permits(Done, Done));
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(serde_json::from_str::<TransitionTable>(&json).unwrap(), table);
+        assert_eq!(TransitionTable::from_compact(&table.to_compact()), Ok(table));
+    }
+
+    #[test]
+    fn history() {
+        let mut vessel = Vessel::new();
+        let id = EntityId::from_parts(at(0), 42);
+        vessel.entity_insert(Entity::new_id(&id));
+        vessel.entity_transit_at(&id, Marching, at(100)).unwrap();
+        vessel.entity_transit_at(&id, Pending, at(160)).unwrap();
+        vessel.entity_transit_at(&id, Marching, at(200)).unwrap();
+        vessel.entity_transit_at(&id, Done, at(230)).unwrap();
+        assert_eq!(vessel.entity_transit_at(&id, New, at(240)), Err(TransitError::Forbidden { from: Done, to: New }));
+        let entity = vessel.entity_get(&id).unwrap();
+        assert_eq!(entity.history.len(), 4);
+        assert_eq!(entity.history[0], Transition { from: New, to: Marching, time: at(100) });
+        assert_eq!(entity.time_in(Marching, at(1000)), Duration::from_secs(90));
+        assert_eq!(entity.time_in(New, at(1000)), Duration::from_secs(100));
+        assert_eq!(entity.time_in(Done, at(1000)), Duration::from_secs(770));
+        assert_eq!(entity.completed_at(), Some(at(230)));
+        assert_eq!(vessel.completed_within(at(200), at(300)), vec![id.clone()]);
+        assert!(vessel.completed_within(at(0), at(230)).is_empty());
+        // paged out entities are found, and stay paged out
+        let parent = vessel.entity_grow();
+        vessel.entity_move(&id, Some(&parent)).unwrap();
+        vessel.page_out(&parent).unwrap();
+        assert_eq!(vessel.completed_within(at(200), at(300)), vec![id.clone()]);
+        assert!(vessel.flow_arena.is_dormant(&parent));
+    }
+
+    #[test]
+    fn this_week() {
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        vessel.transition_table_mut().forbid(New, Done);
+        assert!(vessel.entity_transit(&id, Done).is_err());
+        vessel.entity_transit(&id, Marching).unwrap();
+        vessel.entity_transit(&id, Done).unwrap();
+        assert_eq!(vessel.completed_this_week(), vec![id]);
+        let (start, end) = week_of(SystemTime::now());
+        assert!(start <= SystemTime::now() && SystemTime::now() < end);
+    }
+}
@@ -0,0 +1,336 @@
+//! schedule.rs contains:
+//! 1. Schedule: optional scheduled / due times and a recurrence rule on an Entity.
+//! 2. Recurrence: daily, weekly on weekdays, monthly by day, or every n days after completion.
+//! 3. occurrence expansion over a span, and spawning the next instance on Done.
+//!
+//! a recurring schedule repeats its anchor, i.e. scheduled or else due, at the same time of day in a time zone;
+//! Local unless one is given, as the agenda does.
+
+use std::time::SystemTime;
+use chrono::{Datelike, DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError, Flow, Node};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::journal::Place;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Recurrence {
+    Daily,
+    Weekly(Vec<Weekday>),
+    /// on the given day of month; short months use their last day.
+    Monthly(u32),
+    /// n days after the previous instance was completed.
+    AfterCompletion(u32),
+}
+
+impl Recurrence {
+    /// whether a calendar rule hits date; never for AfterCompletion.
+    fn hits(&self, date: NaiveDate) -> bool {
+        match self {
+            Recurrence::Daily => true,
+            Recurrence::Weekly(days) => days.contains(&date.weekday()),
+            Recurrence::Monthly(day) => {
+                let last = last_day_of_month(date);
+                date.day() == (*day).clamp(1, last)
+            }
+            Recurrence::AfterCompletion(_) => false,
+        }
+    }
+}
+
+fn last_day_of_month(date: NaiveDate) -> u32 {
+    let (y, m) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    NaiveDate::from_ymd_opt(y, m, 1).and_then(|first| first.pred_opt()).map_or(31, |last| last.day())
+}
+
+fn local_date_time<Tz: TimeZone>(time: SystemTime, tz: &Tz) -> (NaiveDate, NaiveTime) {
+    let dt = DateTime::<Utc>::from(time).with_timezone(tz).naive_local();
+    (dt.date(), dt.time())
+}
+
+fn at_local<Tz: TimeZone>(date: NaiveDate, time: NaiveTime, tz: &Tz) -> SystemTime {
+    let naive = date.and_time(time);
+    tz.from_local_datetime(&naive).earliest()
+        .map(SystemTime::from)
+        // Note: a dst gap; take the utc reading.
+        .unwrap_or_else(|| SystemTime::from(Utc.from_utc_datetime(&naive)))
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub scheduled: Option<SystemTime>,
+    pub due: Option<SystemTime>,
+    pub recurrence: Option<Recurrence>,
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.scheduled.is_none() && self.due.is_none()
+    }
+    /// scheduled, or else due.
+    pub fn anchor(&self) -> Option<SystemTime> {
+        self.scheduled.or(self.due)
+    }
+    /// occurrences of the anchor within [start, end), earliest first, with days counted in tz;
+    /// AfterCompletion only ever has the anchor, since the rest depends on completion.
+    pub fn occurrences<Tz: TimeZone>(&self, start: SystemTime, end: SystemTime, tz: &Tz) -> Vec<SystemTime> {
+        let anchor = match self.anchor() {
+            Some(anchor) => anchor,
+            None => return Vec::new(),
+        };
+        let in_span = |t: &SystemTime| start <= *t && *t < end;
+        let rule = match &self.recurrence {
+            Some(rule) if !matches!(rule, Recurrence::AfterCompletion(_)) => rule,
+            _ => return Some(anchor).filter(in_span).into_iter().collect(),
+        };
+        if end <= start { return Vec::new() }
+        let (anchor_date, time) = local_date_time(anchor, tz);
+        let mut date = local_date_time(start.max(anchor), tz).0.max(anchor_date);
+        let last = local_date_time(end, tz).0;
+        let mut res = Vec::new();
+        while date <= last {
+            if date == anchor_date || rule.hits(date) {
+                let t = at_local(date, time, tz);
+                if in_span(&t) { res.push(t) }
+            }
+            date = match date.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        res
+    }
+    /// the anchor of the next instance once this one is completed; None unless recurring.
+    pub fn next_anchor(&self, completed: SystemTime) -> Option<SystemTime> {
+        let anchor = self.anchor()?;
+        let (anchor_date, time) = local_date_time(anchor, &Local);
+        match self.recurrence.as_ref()? {
+            Recurrence::AfterCompletion(n) => {
+                let date = local_date_time(completed, &Local).0 + chrono::Duration::days(*n as i64);
+                Some(at_local(date, time, &Local))
+            }
+            rule => {
+                // Note: a rule with no hit at all, e.g. Weekly(vec![]), ends within a year.
+                let mut date = anchor_date;
+                for _ in 0..366 {
+                    date = date.succ_opt()?;
+                    if rule.hits(date) { return Some(at_local(date, time, &Local)) }
+                }
+                None
+            }
+        }
+    }
+    /// this schedule moved so that its anchor lands on next; scheduled and due keep their gap.
+    fn shifted(&self, next: SystemTime) -> Self {
+        let anchor = self.anchor().unwrap_or(next);
+        let shift = |t: SystemTime| -> SystemTime {
+            match next.duration_since(anchor) {
+                Ok(d) => t + d,
+                Err(e) => t - e.duration(),
+            }
+        };
+        Schedule {
+            scheduled: self.scheduled.map(shift),
+            due: self.due.map(shift),
+            recurrence: self.recurrence.clone(),
+        }
+    }
+}
+
+/// weekdays as days from Monday.
+const WEEKDAYS: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+impl Compact for Recurrence {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Recurrence::Daily => 0u8.encode(buf),
+            Recurrence::Weekly(days) => {
+                1u8.encode(buf);
+                days.iter().map(|day| day.num_days_from_monday() as u8).collect::<Vec<u8>>().encode(buf)
+            }
+            Recurrence::Monthly(day) => { 2u8.encode(buf); day.encode(buf) }
+            Recurrence::AfterCompletion(n) => { 3u8.encode(buf); n.encode(buf) }
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        match u8::decode(src)? {
+            0 => Ok(Recurrence::Daily),
+            1 => {
+                let days: Vec<u8> = Vec::decode(src)?;
+                days.into_iter()
+                    .map(|t| WEEKDAYS.get(t as usize).copied().ok_or(CompactError::InvalidTag(t)))
+                    .collect::<Result<_, _>>()
+                    .map(Recurrence::Weekly)
+            }
+            2 => Ok(Recurrence::Monthly(u32::decode(src)?)),
+            3 => Ok(Recurrence::AfterCompletion(u32::decode(src)?)),
+            t => Err(CompactError::InvalidTag(t)),
+        }
+    }
+}
+
+impl Compact for Schedule {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.scheduled.encode(buf);
+        self.due.encode(buf);
+        self.recurrence.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(Schedule {
+            scheduled: Compact::decode(src)?,
+            due: Compact::decode(src)?,
+            recurrence: Compact::decode(src)?,
+        })
+    }
+}
+
+impl Vessel {
+    /// every occurrence within [start, end) over all entities, earliest first; days are local.
+    pub fn occurrences(&mut self, start: SystemTime, end: SystemTime) -> Vec<(EntityId, SystemTime)> {
+        self.scan(|vessel| {
+            let node_map = &vessel.flow_arena.node_map;
+            let mut res: Vec<(EntityId, SystemTime)> = vessel.entity_preorder().into_iter().flat_map(|id| {
+                let times = node_map[&id].entity.schedule.occurrences(start, end, &Local);
+                times.into_iter().map(move |t| (id.clone(), t))
+            }).collect();
+            res.sort_by_key(|(_, t)| *t);
+            res
+        })
+    }
+    /// puts the next instance of a recurring id right after it, as New;
+    /// id keeps its schedule but stops recurring. None if id doesn't recur.
+    /// a top-level root gets its next instance planted after it, as `<name> (2)` and so on.
+    pub fn entity_spawn_next(&mut self, id: &EntityId, completed: SystemTime) -> Option<EntityId> {
+        let entity = self.entity_get(id)?.clone();
+        let next = entity.schedule.next_anchor(completed)?;
+        let arena = &self.flow_arena;
+        let place = match arena.node(id)?.parent.clone() {
+            Some(parent) => {
+                let nth = arena.node(&parent)?.children.iter().position(|x| x == id)? + 1;
+                Place::Under { parent, nth }
+            }
+            None => {
+                let nth = arena.forest.iter().position(|tree| tree.id == *id)?;
+                let name = (2..).map(|n| format!("{} ({})", arena.forest[nth].name, n))
+                    .find(|name| arena.root_named(name).is_none())
+                    .expect("a free name");
+                Place::Planted { name, nth: Some(nth + 1) }
+            }
+        };
+        let mut spawn = Entity::new_time(&mut self.id_factory);
+        spawn.face = entity.face;
+        spawn.bubble = entity.bubble;
+        spawn.tags = entity.tags;
+        spawn.schedule = entity.schedule.shifted(next);
+        let spawned = spawn.id().clone();
+        self.entity_get_mut(id)?.schedule.recurrence = None;
+        match place {
+            Place::Under { parent, nth } => {
+                self.flow_arena.grow(Node::from_id(spawned.clone(), spawn)).ok();
+                self.flow_arena.relocate(&spawned, &parent, nth).ok();
+            }
+            Place::Planted { name, nth } => {
+                self.flow_arena.plant_at(&name, Node::from_id(spawned.clone(), spawn), nth.unwrap_or_default()).ok();
+            }
+        }
+        self.touch(&spawned);
+        Some(spawned)
+    }
+    pub(crate) fn on_transit(&mut self, id: &EntityId, to: ProcessStatus, time: SystemTime) {
+        if to == ProcessStatus::Done {
+            self.entity_spawn_next(id, time);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(y: i32, m: u32, d: u32, h: u32) -> SystemTime {
+        at_local(NaiveDate::from_ymd_opt(y, m, d).unwrap(), NaiveTime::from_hms_opt(h, 0, 0).unwrap(), &Local)
+    }
+
+    #[test]
+    fn occurrences() {
+        let mut schedule = Schedule { scheduled: Some(local(2021, 3, 1, 9)), ..Schedule::default() };
+        let (start, end) = (local(2021, 3, 1, 0), local(2021, 4, 1, 0));
+        assert_eq!(schedule.occurrences(start, end, &Local), vec![local(2021, 3, 1, 9)]);
+        schedule.recurrence = Some(Recurrence::Daily);
+        assert_eq!(schedule.occurrences(start, end, &Local).len(), 31);
+        schedule.recurrence = Some(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Thu]));
+        // 2021-03-01 is a Monday
+        assert_eq!(schedule.occurrences(start, local(2021, 3, 8, 0), &Local), vec![local(2021, 3, 1, 9), local(2021, 3, 4, 9)]);
+        schedule.recurrence = Some(Recurrence::Monthly(31));
+        assert_eq!(schedule.occurrences(local(2021, 3, 2, 0), local(2021, 5, 1, 0), &Local), vec![local(2021, 3, 31, 9), local(2021, 4, 30, 9)]);
+        schedule.recurrence = Some(Recurrence::AfterCompletion(3));
+        assert_eq!(schedule.occurrences(start, end, &Local), vec![local(2021, 3, 1, 9)]);
+        assert_eq!(schedule.next_anchor(local(2021, 3, 5, 20)), Some(local(2021, 3, 8, 9)));
+        let bytes = schedule.to_compact();
+        assert_eq!(Schedule::from_compact(&bytes), Ok(schedule));
+    }
+
+    #[test]
+    fn spawn_on_done() {
+        let mut vessel = Vessel::new();
+        let parent = vessel.entity_grow();
+        let id = vessel.entity_grow();
+        let other = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&id, &parent).ok();
+        vessel.flow_arena.devote_push(&other, &parent).ok();
+        let entity = vessel.entity_get_mut(&id).unwrap();
+        entity.face = "Water the plants".to_string();
+        entity.schedule = Schedule {
+            scheduled: Some(local(2021, 3, 1, 9)),
+            due: Some(local(2021, 3, 1, 18)),
+            recurrence: Some(Recurrence::Weekly(vec![Weekday::Mon, Weekday::Thu])),
+        };
+        vessel.entity_transit_at(&id, ProcessStatus::Done, local(2021, 3, 1, 10)).unwrap();
+        let children = vessel.flow_arena.node(&parent).unwrap().children.clone();
+        assert_eq!(children.len(), 3);
+        assert_eq!((&children[0], &children[2]), (&id, &other));
+        let spawned = vessel.entity_get(&children[1]).unwrap().clone();
+        assert_eq!(spawned.face, "Water the plants");
+        assert_eq!(spawned.process, ProcessStatus::New);
+        assert_eq!(spawned.schedule.scheduled, Some(local(2021, 3, 4, 9)));
+        assert_eq!(spawned.schedule.due, Some(local(2021, 3, 4, 18)));
+        assert_eq!(vessel.entity_get(&id).unwrap().schedule.recurrence, None);
+        // done again after reopening doesn't spawn twice
+        vessel.entity_transit_at(&id, ProcessStatus::Marching, local(2021, 3, 1, 11)).unwrap();
+        vessel.entity_transit_at(&id, ProcessStatus::Done, local(2021, 3, 1, 12)).unwrap();
+        assert_eq!(vessel.flow_arena.node(&parent).unwrap().children.len(), 3);
+        let found = vessel.occurrences(local(2021, 3, 1, 0), local(2021, 3, 8, 0));
+        assert_eq!(found, vec![(id, local(2021, 3, 1, 9)), (spawned.id().clone(), local(2021, 3, 4, 9))]);
+        // paged out entities are expanded, and stay paged out
+        vessel.page_out(&parent).unwrap();
+        assert_eq!(vessel.occurrences(local(2021, 3, 1, 0), local(2021, 3, 8, 0)), found);
+        assert!(vessel.flow_arena.is_dormant(&parent));
+    }
+
+    #[test]
+    fn spawn_in_place() {
+        let mut vessel = Vessel::new();
+        let schedule = Schedule {
+            scheduled: Some(local(2021, 3, 1, 9)),
+            recurrence: Some(Recurrence::Daily),
+            ..Schedule::default()
+        };
+        // at the top level, right after the original
+        let ids: Vec<EntityId> = (0..3).map(|_| vessel.entity_grow()).collect();
+        vessel.entity_get_mut(&ids[1]).unwrap().schedule = schedule.clone();
+        let spawned = vessel.entity_spawn_next(&ids[1], local(2021, 3, 1, 10)).unwrap();
+        let root = vessel.flow_arena.root.clone();
+        assert_eq!(vessel.entity_children(&root), vec![ids[0].clone(), ids[1].clone(), spawned, ids[2].clone()]);
+        // a top-level root stays in the forest, right after the original
+        let work = vessel.root_plant("work").unwrap();
+        vessel.root_plant("home").unwrap();
+        vessel.entity_get_mut(&work).unwrap().schedule = schedule;
+        let spawned = vessel.entity_spawn_next(&work, local(2021, 3, 1, 10)).unwrap();
+        let names: Vec<&str> = vessel.root_list().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["work", "work (2)", "home"]);
+        assert_eq!(vessel.root_named("work (2)"), Some(&spawned));
+    }
+}
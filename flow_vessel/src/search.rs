@@ -0,0 +1,488 @@
+//! search.rs contains:
+//! 1. tokenize: lowercased alphanumeric runs; CJK characters stand alone.
+//! 2. SearchIndex: an inverted index over Entity::face and Entity::bubble.
+//! 3. Query: space separated terms, all of which must match;
+//!    `"a phrase"` matches consecutive terms, `pre*` matches by prefix.
+//!
+//! Vessel keeps its index lazily: edits only mark entities dirty, and a search refreshes them first.
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, ops::Range};
+use flow_arena::Flow;
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::Entity;
+
+/// face matches weigh more than bubble matches.
+const FACE_WEIGHT: f64 = 2.0;
+const BUBBLE_WEIGHT: f64 = 1.0;
+/// bytes of context kept on each side of the first match in a snippet.
+const SNIPPET_CONTEXT: usize = 40;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    /// byte range in the source text.
+    pub span: Range<usize>,
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff      // kana
+        | 0x3400..=0x4dbf    // cjk ext a
+        | 0x4e00..=0x9fff    // cjk unified
+        | 0xac00..=0xd7af    // hangul
+        | 0xf900..=0xfaff    // cjk compatibility
+    )
+}
+
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    let push = |tokens: &mut Vec<Token>, span: Range<usize>| {
+        tokens.push(Token { term: text[span.clone()].to_lowercase(), span })
+    };
+    for (i, c) in text.char_indices() {
+        if is_cjk(c) {
+            if let Some(s) = start.take() { push(&mut tokens, s..i) }
+            push(&mut tokens, i..i + c.len_utf8());
+        } else if c.is_alphanumeric() {
+            start.get_or_insert(i);
+        } else if let Some(s) = start.take() {
+            push(&mut tokens, s..i)
+        }
+    }
+    if let Some(s) = start { push(&mut tokens, s..text.len()) }
+    tokens
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Face,
+    Bubble,
+}
+
+impl Field {
+    fn weight(&self) -> f64 {
+        match self {
+            Field::Face => FACE_WEIGHT,
+            Field::Bubble => BUBBLE_WEIGHT,
+        }
+    }
+}
+
+/// matched byte ranges, each in a field.
+type Spans = Vec<(Field, Range<usize>)>;
+
+#[derive(Clone, Debug)]
+struct Posting {
+    field: Field,
+    /// token position within the field.
+    pos: usize,
+    span: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Doc {
+    face: String,
+    bubble: String,
+    terms: HashSet<String>,
+}
+
+impl Doc {
+    fn text(&self, field: Field) -> &str {
+        match field {
+            Field::Face => &self.face,
+            Field::Bubble => &self.bubble,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchIndex {
+    /// term -> entity -> postings; ordered so that prefixes are ranges.
+    postings: BTreeMap<String, HashMap<EntityId, Vec<Posting>>>,
+    docs: HashMap<EntityId, Doc>,
+    dirty: HashSet<EntityId>,
+    /// the whole index must be rebuilt, e.g. right after loading.
+    stale: bool,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        SearchIndex {
+            postings: BTreeMap::new(),
+            docs: HashMap::new(),
+            dirty: HashSet::new(),
+            stale: true,
+        }
+    }
+}
+
+impl SearchIndex {
+    /// an up to date index of entities.
+    pub(crate) fn build<'a>(entities: impl IntoIterator<Item = &'a Entity>) -> Self {
+        let mut index = SearchIndex { stale: false, ..SearchIndex::default() };
+        for entity in entities {
+            index.insert(entity);
+        }
+        index
+    }
+    pub fn insert(&mut self, entity: &Entity) {
+        let id = entity.id();
+        self.remove(id);
+        let mut terms = HashSet::new();
+        for (field, text) in [(Field::Face, &entity.face), (Field::Bubble, &entity.bubble)].iter() {
+            for (pos, token) in tokenize(text).into_iter().enumerate() {
+                terms.insert(token.term.clone());
+                self.postings.entry(token.term).or_default()
+                    .entry(id.clone()).or_default()
+                    .push(Posting { field: *field, pos, span: token.span });
+            }
+        }
+        self.docs.insert(id.clone(), Doc {
+            face: entity.face.clone(),
+            bubble: entity.bubble.clone(),
+            terms,
+        });
+    }
+    pub fn remove(&mut self, id: &EntityId) {
+        if let Some(doc) = self.docs.remove(id) {
+            for term in doc.terms.iter() {
+                let empty = self.postings.get_mut(term).map(|map| {
+                    map.remove(id);
+                    map.is_empty()
+                });
+                if empty == Some(true) {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+    /// marks id to be re-indexed before the next search.
+    pub fn touch(&mut self, id: &EntityId) {
+        if !self.stale {
+            self.dirty.insert(id.clone());
+        }
+    }
+    /// matched spans of clause, per entity.
+    fn matches(&self, clause: &Clause) -> HashMap<&EntityId, Spans> {
+        let mut res: HashMap<&EntityId, Spans> = HashMap::new();
+        match clause {
+            Clause::Term(term) => {
+                for (id, vec) in self.postings.get(term).into_iter().flatten() {
+                    res.entry(id).or_default().extend(vec.iter().map(|p| (p.field, p.span.clone())));
+                }
+            }
+            Clause::Prefix(prefix) => {
+                let range = self.postings.range::<String, _>(prefix.clone()..)
+                    .take_while(|(term, _)| term.starts_with(prefix.as_str()));
+                for (_, map) in range {
+                    for (id, vec) in map.iter() {
+                        res.entry(id).or_default().extend(vec.iter().map(|p| (p.field, p.span.clone())));
+                    }
+                }
+            }
+            Clause::Phrase(terms) => {
+                let maps: Option<Vec<&HashMap<EntityId, Vec<Posting>>>> = terms.iter()
+                    .map(|term| self.postings.get(term))
+                    .collect();
+                let maps = match maps {
+                    Some(maps) if !maps.is_empty() => maps,
+                    _ => return res,
+                };
+                for (id, starts) in maps[0].iter() {
+                    for start in starts.iter() {
+                        let mut end = start.span.end;
+                        let chained = maps[1..].iter().enumerate().all(|(k, map)| {
+                            let next = map.get(id).into_iter().flatten()
+                                .find(|p| p.field == start.field && p.pos == start.pos + k + 1);
+                            next.map(|p| end = p.span.end).is_some()
+                        });
+                        if chained {
+                            res.entry(id).or_default().push((start.field, start.span.start..end));
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+    /// ranked hits for query, best first; ties broken by face.
+    pub fn query(&self, query: &Query) -> Vec<(EntityId, f64, Vec<Snippet>)> {
+        let total = self.docs.len().max(1) as f64;
+        let mut scores: HashMap<&EntityId, (f64, Spans)> = HashMap::new();
+        for (k, clause) in query.clauses.iter().enumerate() {
+            let matched = self.matches(clause);
+            let idf = (1.0 + total / matched.len().max(1) as f64).ln();
+            let mut next = HashMap::new();
+            for (id, spans) in matched.into_iter() {
+                let prev = if k == 0 { Some((0.0, Vec::new())) } else { scores.remove(id) };
+                if let Some((score, mut all)) = prev {
+                    let tf: f64 = spans.iter().map(|(field, _)| field.weight()).sum();
+                    all.extend(spans);
+                    next.insert(id, (score + tf * idf, all));
+                }
+            }
+            scores = next;
+        }
+        let mut hits: Vec<(EntityId, f64, Vec<Snippet>)> = scores.into_iter().map(|(id, (score, spans))| {
+            let doc = &self.docs[id];
+            let snippets = [Field::Face, Field::Bubble].iter().filter_map(|field| {
+                let ranges: Vec<Range<usize>> = spans.iter()
+                    .filter(|(f, _)| f == field)
+                    .map(|(_, span)| span.clone())
+                    .collect();
+                Snippet::cut(*field, doc.text(*field), ranges)
+            }).collect();
+            (id.clone(), score, snippets)
+        }).collect();
+        hits.sort_by(|a, b| {
+            b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| self.docs[&a.0].face.cmp(&self.docs[&b.0].face))
+        });
+        hits
+    }
+}
+
+/// a piece of face or bubble around the matches; highlights are byte ranges into text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub field: Field,
+    pub text: String,
+    pub highlights: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    fn cut(field: Field, source: &str, mut ranges: Vec<Range<usize>>) -> Option<Self> {
+        if ranges.is_empty() { return None }
+        ranges.sort_by_key(|r| (r.start, r.end));
+        let floor = |mut i: usize| { while !source.is_char_boundary(i) { i -= 1 } i };
+        let ceil = |mut i: usize| { while !source.is_char_boundary(i) { i += 1 } i };
+        let from = floor(ranges[0].start.saturating_sub(SNIPPET_CONTEXT));
+        let to = ceil((ranges[0].end + SNIPPET_CONTEXT).min(source.len()));
+        let (head, tail) = (if from > 0 { "…" } else { "" }, if to < source.len() { "…" } else { "" });
+        let text = format!("{}{}{}", head, &source[from..to], tail);
+        let mut highlights: Vec<Range<usize>> = Vec::new();
+        for r in ranges.into_iter().filter(|r| r.start >= from && r.end <= to) {
+            let r = (r.start - from + head.len())..(r.end - from + head.len());
+            match highlights.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => highlights.push(r),
+            }
+        }
+        Some(Snippet { field, text, highlights })
+    }
+    /// the text with every highlight wrapped in open and close, e.g. `**` and `**`.
+    pub fn marked(&self, open: &str, close: &str) -> String {
+        let mut res = String::new();
+        let mut last = 0;
+        for r in self.highlights.iter() {
+            res.push_str(&self.text[last..r.start]);
+            res.push_str(open);
+            res.push_str(&self.text[r.clone()]);
+            res.push_str(close);
+            last = r.end;
+        }
+        res.push_str(&self.text[last..]);
+        res
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    clauses: Vec<Clause>,
+}
+
+impl Query {
+    /// never fails; an unclosed quote runs to the end, and punctuation is ignored like in tokenize.
+    pub fn parse(src: &str) -> Self {
+        let mut clauses = Vec::new();
+        let mut quoted = false;
+        for part in src.split('"') {
+            if quoted {
+                let terms: Vec<String> = tokenize(part).into_iter().map(|t| t.term).collect();
+                match terms.len() {
+                    0 => (),
+                    1 => clauses.push(Clause::Term(terms.into_iter().next().expect("one term"))),
+                    _ => clauses.push(Clause::Phrase(terms)),
+                }
+            } else {
+                for word in part.split_whitespace() {
+                    let prefix = word.ends_with('*');
+                    let mut terms: Vec<String> = tokenize(word).into_iter().map(|t| t.term).collect();
+                    let last = terms.pop();
+                    clauses.extend(terms.into_iter().map(Clause::Term));
+                    clauses.extend(last.map(|t| if prefix { Clause::Prefix(t) } else { Clause::Term(t) }));
+                }
+            }
+            quoted = !quoted;
+        }
+        Query { clauses }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub id: EntityId,
+    pub score: f64,
+    pub snippets: Vec<Snippet>,
+    /// ancestors from the top of the tree down to the parent; root excluded.
+    pub path: Vec<EntityId>,
+}
+
+impl Vessel {
+    /// brings the index up to date; a stale index scans everything and starts over.
+    fn search_refresh(&mut self) {
+        if self.search.stale {
+            self.search = self.scan(|vessel| {
+                let root = &vessel.flow_arena.root;
+                SearchIndex::build(vessel.flow_arena.entities().filter(|entity| entity.id() != root))
+            });
+        } else {
+            let dirty: Vec<EntityId> = self.search.dirty.drain().collect();
+            for id in dirty.iter() {
+                // Note: an edited entity may be paged out since.
+                self.flow_arena.materialize(id).ok();
+                match self.flow_arena.node(id) {
+                    Some(node) if id != &self.flow_arena.root => self.search.insert(&node.entity),
+                    _ => self.search.remove(id),
+                }
+            }
+        }
+    }
+    /// ancestors of id from the top down, root excluded.
+    pub fn entity_path(&self, id: &EntityId) -> Vec<EntityId> {
+        let mut path = Vec::new();
+        let mut cur = self.flow_arena.node(id).and_then(|node| node.parent.clone());
+        while let Some(parent) = cur {
+            if parent == self.flow_arena.root { break }
+            cur = self.flow_arena.node(&parent).and_then(|node| node.parent.clone());
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+    pub fn search(&mut self, query: &str) -> Vec<SearchHit> {
+        let query = Query::parse(query);
+        if query.is_empty() { return Vec::new() }
+        self.search_refresh();
+        self.search.query(&query).into_iter().map(|(id, score, snippets)| {
+            // Note: only the chunks holding hits are paged in, for their paths.
+            self.flow_arena.materialize(&id).ok();
+            let path = self.entity_path(&id);
+            SearchHit { id, score, snippets, path }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let terms: Vec<String> = tokenize("Hello, World! flow.er 中文abc").into_iter().map(|t| t.term).collect();
+        assert_eq!(terms, vec!["hello", "world", "flow", "er", "中", "文", "abc"]);
+        let tokens = tokenize("  Ünïcode ok");
+        assert_eq!(tokens[0], Token { term: "ünïcode".to_string(), span: 2..11 });
+    }
+
+    #[test]
+    fn query_parse() {
+        let query = Query::parse(r#"plan* "weekly review" x-ray "unclosed"#);
+        assert_eq!(query.clauses, vec![
+            Clause::Prefix("plan".to_string()),
+            Clause::Phrase(vec!["weekly".to_string(), "review".to_string()]),
+            Clause::Term("x".to_string()),
+            Clause::Term("ray".to_string()),
+            Clause::Term("unclosed".to_string()),
+        ]);
+        assert!(Query::parse(" \"\" ,,").is_empty());
+    }
+
+    fn make_vessel() -> (Vessel, Vec<EntityId>) {
+        let mut vessel = Vessel::new();
+        let texts = [
+            ("Weekly review", "Go over the planning board and review the week."),
+            ("Planning", "Plan the next sprint; weekly sync on Monday."),
+            ("Groceries", "Milk, eggs, and a review of the pantry."),
+        ];
+        let ids: Vec<EntityId> = texts.iter().map(|(face, bubble)| {
+            let id = vessel.entity_grow();
+            let entity = vessel.entity_get_mut(&id).unwrap();
+            entity.face = face.to_string();
+            entity.bubble = bubble.to_string();
+            id
+        }).collect();
+        vessel.flow_arena.devote_push(&ids[1], &ids[0]).ok();
+        (vessel, ids)
+    }
+
+    #[test]
+    fn ranked() {
+        let (mut vessel, ids) = make_vessel();
+        let hits = vessel.search("review");
+        let found: Vec<&EntityId> = hits.iter().map(|hit| &hit.id).collect();
+        // face and bubble both match in the first
+        assert_eq!(found, vec![&ids[0], &ids[2]]);
+        assert_eq!(hits[0].snippets[0].marked("[", "]"), "Weekly [review]");
+        assert_eq!(hits[1].snippets[0].marked("[", "]"), "Milk, eggs, and a [review] of the pantry.");
+    }
+
+    #[test]
+    fn phrase_and_prefix() {
+        let (mut vessel, ids) = make_vessel();
+        let hits = vessel.search("\"weekly review\"");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, ids[0]);
+        let hits = vessel.search("plan*");
+        assert_eq!(hits.iter().map(|hit| &hit.id).collect::<Vec<_>>(), vec![&ids[1], &ids[0]]);
+        assert_eq!(hits[0].path, vec![ids[0].clone()]);
+        assert_eq!(hits[0].snippets[1].marked("<", ">"), "<Plan> the next sprint; weekly sync on Monday.");
+        assert!(vessel.search("plan* groceries").is_empty());
+    }
+
+    #[test]
+    fn edits() {
+        let (mut vessel, ids) = make_vessel();
+        assert_eq!(vessel.search("pantry").len(), 1);
+        vessel.entity_get_mut(&ids[2]).unwrap().bubble = "Bread.".to_string();
+        assert!(vessel.search("pantry").is_empty());
+        assert_eq!(vessel.search("bread").len(), 1);
+        vessel.entity_decay(&ids[2]);
+        assert!(vessel.search("bread").is_empty());
+        let id = vessel.entity_grow();
+        vessel.entity_get_mut(&id).unwrap().face = "Fresh bread".to_string();
+        assert_eq!(vessel.search("bread")[0].id, id);
+    }
+
+    #[test]
+    fn lazy() {
+        let (mut vessel, ids) = make_vessel();
+        vessel.page_out_below(1).unwrap();
+        // the index is built over everything, which is paged out again
+        assert_eq!(vessel.search("pantry")[0].id, ids[2]);
+        assert!(vessel.flow_arena.is_dormant(&ids[0]));
+        let hits = vessel.search("sprint");
+        assert_eq!((&hits[0].id, &hits[0].path), (&ids[1], &vec![ids[0].clone()]));
+    }
+
+    #[test]
+    fn snippet_window() {
+        let long = format!("{}needle{}", "汉".repeat(30), "x ".repeat(40));
+        let snippet = Snippet::cut(Field::Bubble, &long, vec![Range { start: 90, end: 96 }]).unwrap();
+        assert!(snippet.text.starts_with('…') && snippet.text.ends_with('…'));
+        assert_eq!(&snippet.text[snippet.highlights[0].clone()], "needle");
+    }
+}
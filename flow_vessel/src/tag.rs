@@ -1,8 +1,29 @@
+//! tag.rs contains:
+//! 1. TagSet: tags are `/`-separated paths; `work/clientA/urgent` matches `work`.
+//! 2. the vessel-wide tag registry, with rename / merge across all entities.
+//! 3. TagQuery: boolean queries like `work & !done | urgent`;
+//!    `!` binds tighter than `&`, which binds tighter than `|`.
+
 use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError};
+use std::{collections::BTreeMap, fmt};
+
+use super::Vessel;
+use super::identity::EntityId;
 
 pub type Tag = String;
 
-#[derive(Default, Clone, Debug, Deserialize, Serialize)]
+/// trims every segment and drops the empty ones: ` work//a/ ` -> `work/a`.
+pub fn normalize(tag: &str) -> Tag {
+    tag.split('/').map(str::trim).filter(|seg| !seg.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+/// whether tag is pattern or lies below it.
+pub fn tag_matches(tag: &str, pattern: &str) -> bool {
+    tag.strip_prefix(pattern).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct TagSet {
     data: Vec<Tag>,
 }
@@ -17,24 +38,49 @@ impl TagSet {
     pub fn position(&self, tag: &Tag) -> Option<usize> {
         self.into_iter().position(|x| x.clone() == tag.clone())
     }
-    fn contains(&self, tag: &Tag) -> bool {
-        self.position(&tag).is_some()
+    /// exact membership.
+    pub fn contains(&self, tag: &Tag) -> bool {
+        self.data.iter().any(|x| x == tag)
+    }
+    /// hierarchical membership: some tag is pattern or lies below it.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = normalize(pattern);
+        self.data.iter().any(|x| tag_matches(x, &pattern))
+    }
+    /// renames from and everything below it to sit under to, merging duplicates;
+    /// returns whether anything changed.
+    pub fn rename(&mut self, from: &str, to: &str) -> bool {
+        let (from, to) = (normalize(from), normalize(to));
+        if from.is_empty() || to.is_empty() || !self.matches(&from) { return false }
+        let data = std::mem::take(&mut self.data);
+        for tag in data.into_iter() {
+            let tag = if tag_matches(&tag, &from) {
+                format!("{}{}", to, &tag[from.len()..])
+            } else {
+                tag
+            };
+            self.push(tag);
+        }
+        true
     }
     pub fn insert(&mut self, index: usize, tag: Tag) -> bool {
-        let inserting = !self.contains(&tag) && index <= self.data.len();
+        let tag = normalize(&tag);
+        let inserting = !tag.is_empty() && !self.contains(&tag) && index <= self.data.len();
         if inserting {
             self.data.insert(index, tag);
         }
         inserting
     }
     pub fn push(&mut self, tag: Tag) -> bool {
-        let inserting = !self.contains(&tag);
+        let tag = normalize(&tag);
+        let inserting = !tag.is_empty() && !self.contains(&tag);
         if inserting {
             self.data.push(tag);
         }
         inserting
     }
     pub fn remove(&mut self, tag: Tag) -> bool {
+        let tag = normalize(&tag);
         let position = self.position(&tag);
         let removing = position.is_some();
         if let Some(i) = position {
@@ -52,9 +98,244 @@ impl TagSet {
     }
 }
 
+impl Compact for TagSet {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.data.encode(buf)
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(TagSet { data: Vec::decode(src)? })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum TagSetField {
     AddTag(Tag),
     DelTag(Tag),
     ClearTag,
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagQuery {
+    Tag(Tag),
+    Not(Box<TagQuery>),
+    And(Box<TagQuery>, Box<TagQuery>),
+    Or(Box<TagQuery>, Box<TagQuery>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagQueryError {
+    Empty,
+    UnexpectedEnd,
+    /// byte position and the offending char.
+    Unexpected(usize, char),
+    /// byte position of the unclosed `(`.
+    Unclosed(usize),
+}
+
+impl fmt::Display for TagQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TagQueryError::*;
+        match self {
+            Empty => write!(f, "empty tag query"),
+            UnexpectedEnd => write!(f, "tag query ends unexpectedly"),
+            Unexpected(pos, c) => write!(f, "unexpected '{}' at {} in tag query", c, pos),
+            Unclosed(pos) => write!(f, "unclosed '(' at {} in tag query", pos),
+        }
+    }
+}
+
+impl std::error::Error for TagQueryError {}
+
+fn is_tag_char(c: char) -> bool {
+    !c.is_whitespace() && !"&|!()".contains(c)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.src[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+    fn bump(&mut self) {
+        self.pos += self.peek().map_or(0, char::len_utf8);
+    }
+    fn or(&mut self) -> Result<TagQuery, TagQueryError> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some('|') {
+            self.bump();
+            lhs = TagQuery::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+    fn and(&mut self) -> Result<TagQuery, TagQueryError> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some('&') {
+            self.bump();
+            lhs = TagQuery::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Result<TagQuery, TagQueryError> {
+        match self.peek() {
+            None => Err(TagQueryError::UnexpectedEnd),
+            Some('!') => {
+                self.bump();
+                Ok(TagQuery::Not(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                let open = self.pos;
+                self.bump();
+                let inner = self.or()?;
+                match self.peek() {
+                    Some(')') => { self.bump(); Ok(inner) }
+                    Some(c) => Err(TagQueryError::Unexpected(self.pos, c)),
+                    None => Err(TagQueryError::Unclosed(open)),
+                }
+            }
+            Some(c) if is_tag_char(c) => {
+                let rest = &self.src[self.pos..];
+                let len = rest.find(|c| !is_tag_char(c)).unwrap_or(rest.len());
+                self.pos += len;
+                Ok(TagQuery::Tag(normalize(&rest[..len])))
+            }
+            Some(c) => Err(TagQueryError::Unexpected(self.pos, c)),
+        }
+    }
+}
+
+impl TagQuery {
+    pub fn parse(src: &str) -> Result<Self, TagQueryError> {
+        let mut parser = Parser { src, pos: 0 };
+        if parser.peek().is_none() { return Err(TagQueryError::Empty) }
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(c) => Err(TagQueryError::Unexpected(parser.pos, c)),
+        }
+    }
+    pub fn eval(&self, tags: &TagSet) -> bool {
+        use TagQuery::*;
+        match self {
+            Tag(tag) => tags.matches(tag),
+            Not(q) => !q.eval(tags),
+            And(l, r) => l.eval(tags) && r.eval(tags),
+            Or(l, r) => l.eval(tags) || r.eval(tags),
+        }
+    }
+}
+
+impl Vessel {
+    /// every tag in use and each of its ancestors, with the number of entities matching it.
+    pub fn tag_registry(&mut self) -> BTreeMap<Tag, usize> {
+        self.scan(|vessel| vessel.tag_count())
+    }
+    fn tag_count(&self) -> BTreeMap<Tag, usize> {
+        let mut registry = BTreeMap::new();
+        for entity in self.flow_arena.entities() {
+            let mut prefixes: Vec<&str> = entity.tags.data.iter().flat_map(|tag| {
+                tag.match_indices('/').map(move |(i, _)| &tag[..i]).chain(std::iter::once(tag.as_str()))
+            }).collect();
+            prefixes.sort_unstable();
+            prefixes.dedup();
+            for prefix in prefixes {
+                *registry.entry(prefix.to_string()).or_insert(0) += 1;
+            }
+        }
+        registry
+    }
+    /// renames from (and everything below it) to to across the vessel;
+    /// renaming onto a tag in use merges the two. returns the number of entities changed.
+    pub fn tag_rename(&mut self, from: &str, to: &str) -> usize {
+        self.scan(|vessel| {
+            let changed: Vec<EntityId> = vessel.flow_arena.node_map.values_mut()
+                .filter_map(|node| node.entity.tags.rename(from, to).then(|| node.id().clone()))
+                .collect();
+            for id in changed.iter() {
+                vessel.touch(id);
+            }
+            changed.len()
+        })
+    }
+    /// entities whose tags satisfy query, in tree order.
+    pub fn tag_query(&mut self, query: &str) -> Result<Vec<EntityId>, TagQueryError> {
+        let query = TagQuery::parse(query)?;
+        Ok(self.scan(|vessel| {
+            let node_map = &vessel.flow_arena.node_map;
+            vessel.entity_preorder().into_iter()
+                .filter(|id| node_map.get(id).is_some_and(|node| query.eval(&node.entity.tags)))
+                .collect()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(src: &[&str]) -> TagSet {
+        let mut set = TagSet::new();
+        src.iter().for_each(|tag| { set.push(tag.to_string()); });
+        set
+    }
+
+    #[test]
+    fn hierarchy() {
+        let mut set = tags(&["work/clientA/urgent", " home// "]);
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec!["work/clientA/urgent", "home"]);
+        assert!(set.matches("work") && set.matches("work/clientA") && set.matches("home"));
+        assert!(!set.matches("work/client") && !set.matches("urgent"));
+        set.push("work/clientB".to_string());
+        assert!(set.rename("work/clientA", "work/clientB"));
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec!["work/clientB/urgent", "home", "work/clientB"]);
+        assert!(set.rename("work/clientB/urgent", "work/clientB"));
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec!["work/clientB", "home"]);
+    }
+
+    #[test]
+    fn query_parse() {
+        use TagQuery::*;
+        let t = |x: &str| Box::new(Tag(x.to_string()));
+        assert_eq!(TagQuery::parse("work & !done | urgent"), Ok(Or(
+            Box::new(And(t("work"), Box::new(Not(t("done"))))),
+            t("urgent"),
+        )));
+        assert_eq!(TagQuery::parse("work & (a/b | c)"), Ok(And(t("work"), Box::new(Or(t("a/b"), t("c"))))));
+        assert_eq!(TagQuery::parse("  "), Err(TagQueryError::Empty));
+        assert_eq!(TagQuery::parse("work &"), Err(TagQueryError::UnexpectedEnd));
+        assert_eq!(TagQuery::parse("(work | x"), Err(TagQueryError::Unclosed(0)));
+        assert_eq!(TagQuery::parse("work done"), Err(TagQueryError::Unexpected(5, 'd')));
+    }
+
+    #[test]
+    fn vessel() {
+        let mut vessel = Vessel::new();
+        let sets = [&["work/clientA/urgent"][..], &["work", "done"], &["home/urgent"], &["urgent"]];
+        let ids: Vec<EntityId> = sets.iter().map(|set| {
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).unwrap().tags = tags(set);
+            id
+        }).collect();
+        assert_eq!(vessel.tag_query("work & !done | urgent").unwrap(), vec![ids[0].clone(), ids[3].clone()]);
+        assert_eq!(vessel.tag_query("home").unwrap(), vec![ids[2].clone()]);
+        let registry = vessel.tag_registry();
+        assert_eq!(registry.get("work"), Some(&2));
+        assert_eq!(registry.get("work/clientA"), Some(&1));
+        assert_eq!(registry.get("urgent"), Some(&1));
+        assert_eq!(vessel.tag_rename("home/urgent", "urgent"), 1);
+        assert_eq!(vessel.tag_query("urgent").unwrap(), vec![ids[2].clone(), ids[3].clone()]);
+        assert_eq!(vessel.tag_registry().get("home"), None);
+        // paged out entities are counted, and stay paged out
+        vessel.entity_move(&ids[1], Some(&ids[0])).unwrap();
+        vessel.page_out(&ids[0]).unwrap();
+        assert_eq!(vessel.tag_registry().get("work"), Some(&2));
+        assert_eq!(vessel.tag_query("done").unwrap(), vec![ids[1].clone()]);
+        assert_eq!(vessel.tag_rename("done", "finished"), 1);
+        assert!(vessel.flow_arena.is_dormant(&ids[0]));
+    }
+}
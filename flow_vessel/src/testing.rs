@@ -0,0 +1,11 @@
+//! testing.rs contains helpers shared by the tests of the crate.
+
+use std::time::{Duration, SystemTime};
+
+/// a day in seconds.
+pub(crate) const DAY: u64 = 24 * 60 * 60;
+
+/// the time secs after the epoch.
+pub(crate) fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
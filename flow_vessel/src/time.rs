@@ -1,6 +1,11 @@
-use std::{fmt::Debug, time::{SystemTime, Duration}};
-use chrono::{DateTime, Local, Utc};
+use std::{collections::BTreeMap, fmt::Debug, time::{SystemTime, Duration, UNIX_EPOCH}};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
 use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError};
+
+use super::Vessel;
+use super::identity::EntityId;
+
 pub trait TimeRep {
     fn human_local_detail(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
     fn human_local(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
@@ -22,59 +27,190 @@ impl TimeRep for SystemTime {
     }
 }
 
-#[derive(Default)]
-#[derive(Clone, Serialize, Deserialize)]
+/// one stretch of work; running while end is None.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub start: SystemTime,
+    pub end: Option<SystemTime>,
+}
+
+impl Session {
+    /// the length up to end, or up to now if running.
+    pub fn length(&self, now: SystemTime) -> Duration {
+        self.end.unwrap_or(now).duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// sessions of work on an entity, oldest first; at most one of them, the last, is running.
+#[derive(Default, Clone, PartialEq, Eq, Serialize)]
 pub struct TimeLog {
-    start: Option<SystemTime>,
-    end: Option<SystemTime>
+    sessions: Vec<Session>,
+}
+
+/// reads both the current form and the older single-interval `{ start, end }`.
+impl<'de> Deserialize<'de> for TimeLog {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Sessions { sessions: Vec<Session> },
+            Single { start: Option<SystemTime>, end: Option<SystemTime> },
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Sessions { sessions } => TimeLog { sessions },
+            Repr::Single { start, end } => TimeLog::from_single(start, end),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerError {
+    Missing,
+    AlreadyRunning,
+    NotRunning,
+}
+
+impl std::fmt::Display for TimerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimerError::Missing => write!(f, "no such entity"),
+            TimerError::AlreadyRunning => write!(f, "a timer is already running"),
+            TimerError::NotRunning => write!(f, "no timer is running"),
+        }
+    }
 }
 
+impl std::error::Error for TimerError {}
+
 impl TimeLog {
     pub fn new() -> TimeLog {
         TimeLog::default()
     }
+    /// the older single-interval form; an end without a start is dropped.
+    fn from_single(start: Option<SystemTime>, end: Option<SystemTime>) -> Self {
+        TimeLog { sessions: start.map(|start| Session { start, end }).into_iter().collect() }
+    }
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+    /// sets the start of the last session, opening one if there's none.
     pub fn set_start(&mut self, start: SystemTime) -> &mut Self {
-        self.start = Some(start);
+        match self.sessions.last_mut() {
+            Some(last) => last.start = start,
+            None => self.sessions.push(Session { start, end: None }),
+        }
         self
     }
+    /// sets the end of the last session; no-op if there's none.
     pub fn set_end(&mut self, end: SystemTime) -> &mut Self {
-        self.end = Some(end);
+        if let Some(last) = self.sessions.last_mut() {
+            last.end = Some(end);
+        }
         self
     }
+    pub fn is_running(&self) -> bool {
+        self.sessions.last().is_some_and(|last| last.end.is_none())
+    }
+    pub fn start_timer(&mut self, now: SystemTime) -> Result<(), TimerError> {
+        if self.is_running() { return Err(TimerError::AlreadyRunning) }
+        self.sessions.push(Session { start: now, end: None });
+        Ok(())
+    }
+    /// closes the running session and returns its length.
+    pub fn stop_timer(&mut self, now: SystemTime) -> Result<Duration, TimerError> {
+        if !self.is_running() { return Err(TimerError::NotRunning) }
+        let last = self.sessions.last_mut().expect("running");
+        last.end = Some(now.max(last.start));
+        Ok(last.length(now))
+    }
+    /// the finished sessions only.
     pub fn length(&self) -> Duration {
-        match (self.start, self.end) {
-            (Some(s), Some(e)) => e.duration_since(s).unwrap_or(Duration::new(0, 0)),
-            _ => Duration::new(0, 0)
+        self.sessions.iter().filter(|s| s.end.is_some()).map(|s| s.length(UNIX_EPOCH)).sum()
+    }
+    /// all sessions, with a running one counted up to now.
+    pub fn total(&self, now: SystemTime) -> Duration {
+        self.sessions.iter().map(|s| s.length(now)).sum()
+    }
+    /// all sessions split at local midnights.
+    pub fn per_day(&self, now: SystemTime) -> BTreeMap<NaiveDate, Duration> {
+        let mut days = BTreeMap::new();
+        for session in self.sessions.iter() {
+            let end = session.end.unwrap_or(now);
+            let mut start = session.start;
+            while start < end {
+                let date = DateTime::<Local>::from(start).date_naive();
+                let next = local_midnight(date.succ_opt().unwrap_or(date));
+                let until = if next > start { next.min(end) } else { end };
+                *days.entry(date).or_insert_with(Duration::default) += until.duration_since(start).unwrap_or_default();
+                start = until;
+            }
+        }
+        days
+    }
+}
+
+/// the first instant of date in local time.
+pub fn local_midnight(date: NaiveDate) -> SystemTime {
+    let naive = date.and_hms_opt(0, 0, 0).expect("midnight");
+    Local.from_local_datetime(&naive).earliest()
+        .map(SystemTime::from)
+        // Note: a dst gap at midnight; take the utc reading.
+        .unwrap_or_else(|| SystemTime::from(Utc.from_utc_datetime(&naive)))
+}
+
+/// sessions: count, then (start, end) for each.
+impl Compact for TimeLog {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.sessions.len() as u64).encode(buf);
+        for session in self.sessions.iter() {
+            session.start.encode(buf);
+            session.end.encode(buf);
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let len = u64::decode(src)?;
+        let mut sessions = Vec::new();
+        for _ in 0..len {
+            let start = SystemTime::decode(src)?;
+            sessions.push(Session { start, end: Option::decode(src)? });
         }
+        Ok(TimeLog { sessions })
+    }
+}
+
+impl TimeLog {
+    /// the older single-interval compact form: start, end.
+    pub(crate) fn decode_single(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let start = Option::decode(src)?;
+        Ok(TimeLog::from_single(start, Option::decode(src)?))
     }
 }
 
 impl TimeRep for TimeLog {
     fn human_local_detail(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[:")?;
-        self.start.map(|t: SystemTime| t.human_local_detail(f));
-        write!(f, " ~ ")?;
-        self.end.map(|t: SystemTime| t.human_local_detail(f));
-        write!(f, ":]")?;
-        write!(f, "")
+        self.human_with(f, TimeRep::human_local_detail)
     }
-
     fn human_local(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[:")?;
-        self.start.map(|t: SystemTime| t.human_local(f));
-        write!(f, " ~ ")?;
-        self.end.map(|t: SystemTime| t.human_local(f));
-        write!(f, ":]")?;
-        write!(f, "")
+        self.human_with(f, TimeRep::human_local)
     }
-
     fn human_utc(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[:")?;
-        self.start.map(|t: SystemTime| t.human_utc(f));
-        write!(f, " ~ ")?;
-        self.end.map(|t: SystemTime| t.human_utc(f));
-        write!(f, ":]")?;
-        write!(f, "")
+        self.human_with(f, TimeRep::human_utc)
+    }
+}
+
+type Human = fn(&SystemTime, &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+
+impl TimeLog {
+    fn human_with(&self, f: &mut std::fmt::Formatter<'_>, human: Human) -> std::fmt::Result {
+        for (i, session) in self.sessions.iter().enumerate() {
+            if i > 0 { write!(f, ", ")? }
+            write!(f, "[:")?;
+            human(&session.start, f)?;
+            write!(f, " ~ ")?;
+            if let Some(end) = session.end { human(&end, f)? }
+            write!(f, ":]")?;
+        }
+        Ok(())
     }
 }
 
@@ -84,10 +220,42 @@ impl Debug for TimeLog {
     }
 }
 
+impl Vessel {
+    /// starts a timer on id, creating its TimeLog if needed.
+    pub fn entity_start_timer(&mut self, id: &EntityId, now: SystemTime) -> Result<(), TimerError> {
+        let entity = self.entity_get_mut(id).ok_or(TimerError::Missing)?;
+        entity.time.get_or_insert_with(TimeLog::new).start_timer(now)
+    }
+    pub fn entity_stop_timer(&mut self, id: &EntityId, now: SystemTime) -> Result<Duration, TimerError> {
+        let entity = self.entity_get_mut(id).ok_or(TimerError::Missing)?;
+        entity.time.as_mut().ok_or(TimerError::NotRunning)?.stop_timer(now)
+    }
+    /// time logged on id and everything below it.
+    pub fn time_total(&mut self, id: &EntityId, now: SystemTime) -> Duration {
+        self.time_logs(id).iter().map(|log| log.total(now)).sum()
+    }
+    /// time logged on id and everything below it, per local day.
+    pub fn time_per_day(&mut self, id: &EntityId, now: SystemTime) -> BTreeMap<NaiveDate, Duration> {
+        let mut days = BTreeMap::new();
+        for log in self.time_logs(id).iter() {
+            for (date, dur) in log.per_day(now) {
+                *days.entry(date).or_insert_with(Duration::default) += dur;
+            }
+        }
+        days
+    }
+    fn time_logs(&mut self, id: &EntityId) -> Vec<&TimeLog> {
+        let subtree = self.entity_subtree(id);
+        let node_map = &self.flow_arena.node_map;
+        subtree.iter().filter_map(|id| node_map.get(id)?.entity.time.as_ref()).collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::thread::sleep;
+    use crate::testing::at;
     #[test]
     fn time_log() {
         let u_dur = 200;
@@ -100,4 +268,65 @@ mod tests {
         assert!(dur > Duration::from_millis(u_dur));
         println!("{:?}", time)
     }
+
+    #[test]
+    fn sessions() {
+        let mut time = TimeLog::new();
+        time.start_timer(at(100)).unwrap();
+        assert_eq!(time.start_timer(at(110)), Err(TimerError::AlreadyRunning));
+        assert_eq!(time.stop_timer(at(130)), Ok(Duration::from_secs(30)));
+        assert_eq!(time.stop_timer(at(140)), Err(TimerError::NotRunning));
+        time.start_timer(at(200)).unwrap();
+        assert!(time.is_running());
+        assert_eq!(time.length(), Duration::from_secs(30));
+        assert_eq!(time.total(at(250)), Duration::from_secs(80));
+        let bytes = time.to_compact();
+        assert_eq!(TimeLog::from_compact(&bytes), Ok(time.clone()));
+        println!("{:?}", time)
+    }
+
+    #[test]
+    fn serde_single() {
+        let json = r#"{"start":{"secs_since_epoch":100,"nanos_since_epoch":0},"end":{"secs_since_epoch":160,"nanos_since_epoch":0}}"#;
+        let time: TimeLog = serde_json::from_str(json).unwrap();
+        assert_eq!(time.sessions(), &[Session { start: at(100), end: Some(at(160)) }]);
+        let time: TimeLog = serde_json::from_str(r#"{"start":null,"end":null}"#).unwrap();
+        assert_eq!(time, TimeLog::new());
+        let mut time = TimeLog::new();
+        time.start_timer(at(1)).unwrap();
+        let json = serde_json::to_string(&time).unwrap();
+        assert_eq!(serde_json::from_str::<TimeLog>(&json).unwrap(), time);
+    }
+
+    #[test]
+    fn per_day() {
+        let day = NaiveDate::from_ymd_opt(2021, 3, 1).unwrap();
+        let midnight = local_midnight(day.succ_opt().unwrap());
+        let mut time = TimeLog::new();
+        time.start_timer(midnight - Duration::from_secs(600)).unwrap();
+        time.stop_timer(midnight + Duration::from_secs(900)).unwrap();
+        let days = time.per_day(midnight);
+        assert_eq!(days.get(&day), Some(&Duration::from_secs(600)));
+        assert_eq!(days.get(&day.succ_opt().unwrap()), Some(&Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn roll_up() {
+        use flow_arena::Flow;
+        let mut vessel = Vessel::new();
+        let id = vessel.entity_grow();
+        let id1 = vessel.entity_grow();
+        let id2 = vessel.entity_grow();
+        vessel.flow_arena.devote_push(&id1, &id).ok();
+        vessel.flow_arena.devote_push(&id2, &id1).ok();
+        vessel.entity_start_timer(&id, at(0)).unwrap();
+        vessel.entity_stop_timer(&id, at(10)).unwrap();
+        vessel.entity_start_timer(&id2, at(100)).unwrap();
+        assert_eq!(vessel.entity_start_timer(&id2, at(101)), Err(TimerError::AlreadyRunning));
+        assert_eq!(vessel.entity_stop_timer(&id1, at(102)), Err(TimerError::NotRunning));
+        vessel.page_out(&id).unwrap();
+        assert_eq!(vessel.time_total(&id, at(130)), Duration::from_secs(40));
+        assert_eq!(vessel.time_total(&id1, at(130)), Duration::from_secs(30));
+        assert_eq!(vessel.time_per_day(&id, at(130)).values().sum::<Duration>(), Duration::from_secs(40));
+    }
 }
@@ -0,0 +1,293 @@
+//! todotxt.rs contains todo.txt import and export, one task per line:
+//! ```text
+//! x 2021-03-02 2021-03-01 Collect numbers +Report @office pri:A
+//! (A) 2021-03-01 Write report +Report @office due:2021-03-05
+//! ```
+//! 1. `x` is Done, with the completion date recorded as a transition;
+//! 2. the creation date is kept as created, and otherwise taken from the time of the id;
+//! 3. the priority becomes the tag `priority/A`, and `@context` a tag;
+//! 4. `due:` and `t:` are the due and scheduled dates, other `key:value`s stay in face;
+//! 5. the first `+project` is the parent: a line faced so, or else a new node; later ones stay in face.
+
+use std::{collections::HashMap, time::SystemTime};
+use chrono::{DateTime, Local, NaiveDate};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::entity::{Entity, ProcessStatus};
+use super::process::Transition;
+use super::time::local_midnight;
+use super::markdown::OutlineError;
+
+const PRIORITY: &str = "priority/";
+const DATE: &str = "%Y-%m-%d";
+
+fn parse_date(word: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(word, DATE).ok().filter(|_| word.len() == 10)
+}
+
+fn format_date(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).date_naive().format(DATE).to_string()
+}
+
+/// the key used to match `+project` against faces.
+fn project_key(face: &str) -> String {
+    face.split_whitespace().collect::<String>().to_lowercase()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TodoLine {
+    done: bool,
+    priority: Option<char>,
+    completed: Option<NaiveDate>,
+    created: Option<NaiveDate>,
+    face: String,
+    project: Option<String>,
+    contexts: Vec<String>,
+    due: Option<NaiveDate>,
+    scheduled: Option<NaiveDate>,
+}
+
+fn parse_line(line: &str) -> Option<TodoLine> {
+    let mut words = line.split_whitespace().peekable();
+    words.peek()?;
+    let mut todo = TodoLine::default();
+    if words.peek() == Some(&"x") {
+        words.next();
+        todo.done = true;
+        todo.completed = words.peek().and_then(|word| parse_date(word));
+        if todo.completed.is_some() { words.next(); }
+    } else if let Some(word) = words.peek() {
+        let mut chars = word.chars();
+        if let (Some('('), Some(p), Some(')'), None) = (chars.next(), chars.next(), chars.next(), chars.next()) {
+            if p.is_ascii_uppercase() {
+                todo.priority = Some(p);
+                words.next();
+            }
+        }
+    }
+    todo.created = words.peek().and_then(|word| parse_date(word));
+    if todo.created.is_some() { words.next(); }
+    let mut face = Vec::new();
+    for word in words {
+        if let Some(project) = word.strip_prefix('+').filter(|p| !p.is_empty() && todo.project.is_none()) {
+            todo.project = Some(project.to_string());
+            continue
+        }
+        if let Some(context) = word.strip_prefix('@').filter(|c| !c.is_empty()) {
+            todo.contexts.push(context.to_string());
+            continue
+        }
+        if let Some((key, value)) = word.split_once(':') {
+            match (key, parse_date(value)) {
+                ("due", Some(date)) => { todo.due = Some(date); continue }
+                ("t", Some(date)) => { todo.scheduled = Some(date); continue }
+                _ => {}
+            }
+            let mut chars = value.chars();
+            if let ("pri", Some(p), None) = (key, chars.next(), chars.next()) {
+                if p.is_ascii_uppercase() && todo.priority.is_none() {
+                    todo.priority = Some(p);
+                    continue
+                }
+            }
+        }
+        face.push(word);
+    }
+    todo.face = face.join(" ");
+    Some(todo)
+}
+
+impl Entity {
+    /// the todo.txt line of self, with project as its `+project`.
+    fn todo_line(&self, project: Option<&str>) -> String {
+        let mut words: Vec<String> = Vec::new();
+        let priority = self.tags.into_iter().find_map(|tag| tag.strip_prefix(PRIORITY).map(str::to_string));
+        let done = self.process == ProcessStatus::Done;
+        if done {
+            words.push("x".to_string());
+            if let Some(completed) = self.completed_at() {
+                words.push(format_date(completed));
+            }
+        } else if let Some(p) = &priority {
+            words.push(format!("({})", p));
+        }
+        if self.created() > std::time::UNIX_EPOCH {
+            words.push(format_date(self.created()));
+        }
+        words.extend(self.face.split_whitespace().map(str::to_string));
+        if let Some(project) = project {
+            words.push(format!("+{}", project.split_whitespace().collect::<String>()));
+        }
+        for tag in self.tags.into_iter().filter(|tag| !tag.starts_with(PRIORITY)) {
+            words.push(format!("@{}", tag.split_whitespace().collect::<Vec<_>>().join("-")));
+        }
+        if let Some(due) = self.schedule.due {
+            words.push(format!("due:{}", format_date(due)));
+        }
+        if let Some(scheduled) = self.schedule.scheduled {
+            words.push(format!("t:{}", format_date(scheduled)));
+        }
+        if let (true, Some(p)) = (done, &priority) {
+            words.push(format!("pri:{}", p));
+        }
+        words.join(" ")
+    }
+}
+
+impl Vessel {
+    /// id and its subtree as todo.txt, each entity's parent as its `+project`; the whole vessel if id is None.
+    pub fn export_todotxt(&mut self, id: Option<&EntityId>) -> String {
+        let top = match id {
+            Some(id) => vec![id.clone()],
+            None => self.entity_top(),
+        };
+        let mut out = String::new();
+        let mut stack: Vec<(EntityId, Option<String>)> = top.into_iter().rev().map(|id| (id, None)).collect();
+        while let Some((id, project)) = stack.pop() {
+            let face = match self.entity_get(&id) {
+                Some(entity) => {
+                    out.push_str(&entity.todo_line(project.as_deref()));
+                    out.push('\n');
+                    entity.face.clone()
+                }
+                None => continue,
+            };
+            stack.extend(self.entity_children(&id).into_iter().rev().map(|child| (child, Some(face.clone()))));
+        }
+        out
+    }
+    /// grows the tasks in a todo.txt as the last children of under, or below root if None;
+    /// returns the created entities, project nodes included, in order of creation.
+    pub fn import_todotxt(&mut self, under: Option<&EntityId>, text: &str) -> Result<Vec<EntityId>, OutlineError> {
+        if let Some(under) = under {
+            if self.entity_get(under).is_none() { return Err(OutlineError::Missing) }
+        }
+        let mut created = Vec::new();
+        let mut lines = Vec::new();
+        for todo in text.lines().filter_map(parse_line) {
+            let mut entity = Entity::new_time(&mut self.id_factory);
+            let id = entity.id().clone();
+            entity.created = todo.created.map(local_midnight);
+            entity.face = todo.face.clone();
+            if todo.done {
+                entity.process = ProcessStatus::Done;
+                if let Some(date) = todo.completed {
+                    entity.history.push(Transition { from: ProcessStatus::New, to: ProcessStatus::Done, time: local_midnight(date) });
+                }
+            }
+            if let Some(p) = todo.priority {
+                entity.tags.push(format!("{}{}", PRIORITY, p));
+            }
+            for context in todo.contexts.iter() {
+                entity.tags.push(context.clone());
+            }
+            entity.schedule.due = todo.due.map(local_midnight);
+            entity.schedule.scheduled = todo.scheduled.map(local_midnight);
+            self.entity_insert(entity);
+            self.entity_move(&id, under).ok();
+            created.push(id.clone());
+            lines.push((id, todo.project));
+        }
+
+        // projects: the first line faced so, else an existing child of under, else a new node
+        let mut projects: HashMap<String, EntityId> = HashMap::new();
+        let existing = match under {
+            Some(under) => self.entity_children(under),
+            None => self.entity_top(),
+        };
+        for id in existing.iter().chain(created.iter()).rev() {
+            if let Some(entity) = self.entity_get(id) {
+                projects.insert(project_key(&entity.face), id.clone());
+            }
+        }
+        let mut parent: HashMap<EntityId, EntityId> = HashMap::new();
+        for (id, project) in lines.iter() {
+            let project = match project {
+                Some(project) => project,
+                None => continue,
+            };
+            let key = project_key(project);
+            let mut node = projects.get(&key).cloned();
+            // a project that is the line itself or lies below it would make a cycle
+            let mut up = node.clone();
+            while let Some(above) = up {
+                if &above == id { node = None; break }
+                up = parent.get(&above).cloned();
+            }
+            let node = match node {
+                Some(node) => node,
+                None => {
+                    let mut entity = Entity::new_time(&mut self.id_factory);
+                    entity.face = project.clone();
+                    let node = self.entity_insert(entity);
+                    self.entity_move(&node, under).ok();
+                    created.push(node.clone());
+                    projects.insert(key, node.clone());
+                    node
+                }
+            };
+            self.entity_move(id, Some(&node)).ok();
+            parent.insert(id.clone(), node);
+        }
+        Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flow_arena::Compact;
+
+    #[test]
+    fn line() {
+        let date = |d: u32| NaiveDate::from_ymd_opt(2021, 3, d);
+        let todo = parse_line("x 2021-03-02 2021-03-01 Collect  numbers +Report +Later @office pri:A url:http://x").unwrap();
+        assert_eq!(todo, TodoLine {
+            done: true,
+            priority: Some('A'),
+            completed: date(2),
+            created: date(1),
+            face: "Collect numbers +Later url:http://x".to_string(),
+            project: Some("Report".to_string()),
+            contexts: vec!["office".to_string()],
+            due: None,
+            scheduled: None,
+        });
+        let todo = parse_line("(B) Write report due:2021-03-05 t:2021-03-04 (C)").unwrap();
+        assert_eq!((todo.priority, todo.face.as_str(), todo.due, todo.scheduled), (Some('B'), "Write report (C)", date(5), date(4)));
+        assert_eq!(parse_line("x 2021-03-02 done").unwrap().completed, date(2));
+        assert!(parse_line("   ").is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "\
+(A) 2021-03-01 Write report @office due:2021-03-05
+x 2021-03-02 2021-03-01 Collect numbers +Writereport @office pri:B
+2021-03-01 Call Bob +Phone
+";
+        let mut vessel = Vessel::new();
+        let home = vessel.entity_grow();
+        let created = vessel.import_todotxt(Some(&home), text).unwrap();
+        // the three lines, then the Phone project
+        assert_eq!(created.len(), 4);
+        assert_eq!(vessel.entity_children(&home), vec![created[0].clone(), created[3].clone()]);
+        assert_eq!(vessel.entity_children(&created[0]), vec![created[1].clone()]);
+        assert_eq!(vessel.entity_children(&created[3]), vec![created[2].clone()]);
+        let numbers = vessel.entity_get(&created[1]).unwrap().clone();
+        assert_eq!(numbers.process, ProcessStatus::Done);
+        assert_eq!(numbers.completed_at(), Some(local_midnight(NaiveDate::from_ymd_opt(2021, 3, 2).unwrap())));
+        // ids come from the factory, in order; the dates are kept aside
+        assert!(created.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(numbers.created, Some(local_midnight(NaiveDate::from_ymd_opt(2021, 3, 1).unwrap())));
+        let mut compact = Vessel::from_compact(&vessel.to_compact()).unwrap();
+        assert_eq!(compact.entity_get(&created[1]).unwrap().created, numbers.created);
+
+        let exported = vessel.export_todotxt(Some(&created[0]));
+        assert_eq!(exported, "\
+(A) 2021-03-01 Write report @office due:2021-03-05
+x 2021-03-02 2021-03-01 Collect numbers +Writereport @office pri:B
+");
+    }
+}
@@ -0,0 +1,170 @@
+//! transaction.rs contains Vessel::transaction, to apply several edits as one:
+//! 1. if the closure fails, the vessel is put back as it was before it ran;
+//! 2. if it succeeds, one ChangeRecord holds the whole edit, both ways, for history and undo.
+//!
+//! a record is two batches in the form the journal replays: the touched entities
+//! as they were before, and as they are after. the id counter is never rolled back by undo,
+//! so that an undone entity's id isn't handed out again.
+
+use serde::{Serialize, Deserialize};
+
+use super::Vessel;
+use super::identity::EntityId;
+use super::journal::{self, Mutation};
+
+/// one edit made by a transaction; see transaction.rs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    undo: Vec<Mutation>,
+    redo: Vec<Mutation>,
+}
+
+impl ChangeRecord {
+    pub fn is_empty(&self) -> bool {
+        self.redo.is_empty()
+    }
+    /// the entities the edit put, moved or removed.
+    pub fn ids(&self) -> Vec<EntityId> {
+        self.redo.iter().filter_map(|mutation| match mutation {
+            Mutation::Put { entity, .. } => Some(entity.id().clone()),
+            Mutation::Remove { id } => Some(id.clone()),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl Vessel {
+    /// runs f on self as one edit: on Err everything f did is undone, on Ok the edit is returned as a record.
+    /// transactions nest; the record of the outer one covers the inner ones.
+    pub fn transaction<T, E>(&mut self, f: impl FnOnce(&mut Vessel) -> Result<T, E>) -> Result<(T, ChangeRecord), E> {
+        let mut before = self.flow_arena.clone();
+        let factory = self.id_factory.clone();
+        let transitions = self.transitions.clone();
+        let trash = self.trash.clone();
+        let archive = self.archive.clone();
+        let outer = std::mem::take(&mut self.changes);
+        let res = f(self);
+        let changes = std::mem::replace(&mut self.changes, outer);
+        match res {
+            Ok(value) => {
+                let mut undo = journal::mutations(&mut before, changes.touched.iter().cloned());
+                let mut redo = journal::mutations(&mut self.flow_arena, changes.touched.iter().cloned());
+                if changes.settings {
+                    undo.push(Mutation::Transitions(transitions));
+                    undo.push(Mutation::Retention(trash.retention));
+                    redo.push(Mutation::Transitions(self.transitions.clone()));
+                    redo.push(Mutation::Retention(self.trash.retention));
+                }
+                undo.extend(trash.mutations(changes.trashed.iter().cloned()));
+                redo.extend(self.trash.mutations(changes.trashed.iter().cloned()));
+                undo.extend(archive.mutations(changes.archived.iter().cloned()));
+                redo.extend(self.archive.mutations(changes.archived.iter().cloned()));
+                self.changes.merge(changes);
+                Ok((value, ChangeRecord { undo, redo }))
+            }
+            Err(e) => {
+                self.flow_arena = before;
+                self.id_factory = factory;
+                self.transitions = transitions;
+                self.trash = trash;
+                self.archive = archive;
+                // Note: the indexes may have seen the edits; the journal hasn't, and needn't.
+                for id in changes.touched.iter() {
+                    self.search.touch(id);
+                    self.links.touch(id);
+                }
+                Err(e)
+            }
+        }
+    }
+    /// reverts the edit of record; it must be the latest edit to its entities.
+    pub fn undo(&mut self, record: &ChangeRecord) {
+        self.replay(&record.undo);
+    }
+    /// applies the edit of record again after undo.
+    pub fn redo(&mut self, record: &ChangeRecord) {
+        self.replay(&record.redo);
+    }
+    fn replay(&mut self, batch: &[Mutation]) {
+        self.apply_batch(batch.to_vec());
+        for mutation in batch {
+            match mutation {
+                Mutation::Put { entity, .. } => self.touch(entity.id()),
+                Mutation::Remove { id } => self.touch(id),
+                Mutation::Transitions(_) | Mutation::Retention(_) => self.changes.settings = true,
+                Mutation::TrashPut { entry, .. } => { self.changes.trashed.insert(entry.id().clone()); }
+                Mutation::TrashRemove { id } => { self.changes.trashed.insert(id.clone()); }
+                Mutation::ArchivePut { entry, .. } => { self.changes.archived.insert(entry.id().clone()); }
+                Mutation::ArchiveRemove { id } => { self.changes.archived.insert(id.clone()); }
+                Mutation::Factory(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ForestError;
+
+    /// each entity in tree order with its parent and face.
+    fn shape(vessel: &mut Vessel) -> Vec<(EntityId, Option<EntityId>, String)> {
+        vessel.entity_preorder().into_iter().map(|id| {
+            let parent = vessel.entity_parent(&id);
+            let face = vessel.entity_get(&id).unwrap().face.clone();
+            (id, parent, face)
+        }).collect()
+    }
+
+    /// splits the children of id after the first into a new sibling named face.
+    fn split(vessel: &mut Vessel, id: &EntityId, face: &str) -> Result<EntityId, ForestError> {
+        let half = vessel.entity_grow();
+        vessel.entity_get_mut(&half).ok_or(ForestError::Missing)?.face = face.to_string();
+        let parent = vessel.entity_parent(id);
+        vessel.entity_move(&half, parent.as_ref())?;
+        for child in vessel.entity_children(id).into_iter().skip(1) {
+            vessel.entity_move(&child, Some(&half))?;
+        }
+        Ok(half)
+    }
+
+    #[test]
+    fn transaction() {
+        let mut vessel = Vessel::new();
+        let list = vessel.entity_grow();
+        vessel.entity_get_mut(&list).unwrap().face = "List".to_string();
+        for face in ["a", "b", "c"] {
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).unwrap().face = face.to_string();
+            vessel.entity_move(&id, Some(&list)).unwrap();
+        }
+        vessel.take_changes();
+        let start = shape(&mut vessel);
+
+        // rolled back: no half-made split is left behind
+        let half = vessel.transaction(|tx| {
+            let half = split(tx, &list, "List, part 2")?;
+            // a cycle, so this step fails
+            let inner = tx.entity_children(&half)[0].clone();
+            tx.entity_move(&half, Some(&inner))?;
+            Ok::<_, ForestError>(half)
+        });
+        assert!(half.is_err());
+        assert_eq!(shape(&mut vessel), start);
+        assert!(vessel.take_changes().is_empty());
+        assert!(vessel.search("part").is_empty());
+
+        let (half, record) = vessel.transaction(|tx| split(tx, &list, "List, part 2")).unwrap();
+        let done = shape(&mut vessel);
+        assert_eq!(vessel.entity_children(&half).len(), 2);
+        assert!(record.ids().contains(&half));
+        assert!(!vessel.take_changes().is_empty());
+
+        vessel.undo(&record);
+        assert_eq!(shape(&mut vessel), start);
+        assert!(vessel.search("part").is_empty());
+        vessel.redo(&record);
+        assert_eq!(shape(&mut vessel), done);
+        assert_eq!(vessel.search("part").len(), 1);
+    }
+}
@@ -0,0 +1,383 @@
+//! trash.rs contains the trash bin of a Vessel:
+//! 1. entity_trash takes an entity out with its subtree, keeping where it was and when;
+//!    entity_trash_alone moves the children up first;
+//! 2. trash_restore puts it back at that place, or at the end of root if the place is gone;
+//! 3. entries older than the retention are purged whenever something is trashed, on load, or by trash_purge.
+//!
+//! the trash is saved with the vessel, but isn't part of the tree: search, links and exports don't see it.
+
+use std::{fmt, time::{Duration, SystemTime}};
+use serde::{Serialize, Deserialize};
+use flow_arena::{Compact, CompactError, Node};
+
+use super::{Vessel, ResolveError, resolve_among};
+use super::identity::EntityId;
+use super::entity::Entity;
+use super::journal::{Mutation, Place};
+
+/// how long trashed entities are kept, by default: 30 days.
+pub const TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// an entity taken out with its subtree.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trashed {
+    /// the first is the trashed entity.
+    nodes: Subtree,
+    place: Place,
+    deleted: SystemTime,
+}
+
+impl Trashed {
+    pub fn id(&self) -> &EntityId {
+        self.nodes[0].0.id()
+    }
+    pub fn face(&self) -> &str {
+        &self.nodes[0].0.face
+    }
+    /// the trashed entity itself.
+    pub fn entity(&self) -> &Entity {
+        &self.nodes[0].0
+    }
+    pub fn deleted(&self) -> SystemTime {
+        self.deleted
+    }
+    /// the former parent; None if it was at the top level.
+    pub fn parent(&self) -> Option<&EntityId> {
+        match &self.place {
+            // Note: the root of a vessel always has the default id.
+            Place::Under { parent, .. } if *parent != EntityId::default() => Some(parent),
+            _ => None,
+        }
+    }
+    /// the number of entities, the subtree included.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Trash {
+    entries: Vec<Trashed>,
+    /// None keeps entries forever.
+    pub(crate) retention: Option<Duration>,
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Trash { entries: Vec::new(), retention: Some(TRASH_RETENTION) }
+    }
+}
+
+impl Trash {
+    /// puts entry as the nth, or last, replacing the one with its id.
+    pub(crate) fn put(&mut self, entry: Trashed, nth: usize) {
+        self.remove(entry.id());
+        self.entries.insert(nth.min(self.entries.len()), entry);
+    }
+    pub(crate) fn remove(&mut self, id: &EntityId) {
+        self.entries.retain(|entry| entry.id() != id);
+    }
+    /// puts for the ids with an entry and removals for the others, as journal::mutations does.
+    pub(crate) fn mutations(&self, ids: impl IntoIterator<Item = EntityId>) -> Vec<Mutation> {
+        let mut batch = Vec::new();
+        let mut put = Vec::new();
+        for id in ids {
+            match self.entries.iter().position(|entry| *entry.id() == id) {
+                Some(nth) => put.push(nth),
+                None => batch.push(Mutation::TrashRemove { id }),
+            }
+        }
+        put.sort();
+        batch.extend(put.into_iter().map(|nth| Mutation::TrashPut { entry: self.entries[nth].clone(), nth }));
+        batch
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrashError {
+    /// no trashed entity has the id.
+    Missing(EntityId),
+    /// an entity of the subtree exists again in the vessel.
+    Occupied(EntityId),
+}
+
+impl fmt::Display for TrashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrashError::Missing(id) => write!(f, "{} is not in the trash", id),
+            TrashError::Occupied(id) => write!(f, "{} exists in the vessel again", id),
+        }
+    }
+}
+
+impl std::error::Error for TrashError {}
+
+/// a subtree in preorder, each entity with the index of its parent; the first has None.
+pub(crate) type Subtree = Vec<(Entity, Option<usize>)>;
+
+impl Vessel {
+    /// takes id and its subtree out of the tree, with the place it had; None if id doesn't exist.
+    pub(crate) fn subtree_take(&mut self, id: &EntityId) -> Option<(Subtree, Place)> {
+        if *id == self.flow_arena.root { return None }
+        let ids = self.entity_subtree(id);
+        if ids.is_empty() { return None }
+        let arena = &self.flow_arena;
+        let node = &arena.node_map[id];
+        let place = match &node.parent {
+            Some(parent) => {
+                let nth = arena.node_map.get(parent)
+                    .and_then(|parent| parent.children.iter().position(|x| x == id))
+                    .unwrap_or_default();
+                Place::Under { parent: parent.clone(), nth }
+            }
+            None => {
+                let nth = arena.forest.iter().position(|tree| tree.id == *id);
+                Place::Planted { name: nth.map(|nth| arena.forest[nth].name.clone()).unwrap_or_default(), nth }
+            }
+        };
+        let nodes = ids.iter().map(|x| {
+            let node = &arena.node_map[x];
+            let parent = node.parent.as_ref().and_then(|parent| ids.iter().position(|y| y == parent));
+            (node.entity.clone(), if x == id { None } else { parent })
+        }).collect();
+        // Note: leaves first, so that nothing moves up on the way.
+        for x in ids.iter().rev() {
+            self.entity_decay(x);
+        }
+        Some((nodes, place))
+    }
+    /// the first entity of nodes that is in the tree already.
+    pub(crate) fn subtree_clash(&mut self, nodes: &Subtree) -> Option<EntityId> {
+        nodes.iter().map(|(entity, _)| entity.id())
+            .find(|id| self.flow_arena.materialize(id).unwrap_or(false))
+            .cloned()
+    }
+    /// puts nodes back at place, or at the end of root if its parent is gone or its name is taken;
+    /// see subtree_clash first.
+    pub(crate) fn subtree_put(&mut self, nodes: Subtree, place: &Place) {
+        let ids: Vec<EntityId> = nodes.iter().map(|(entity, _)| entity.id().clone()).collect();
+        for (entity, parent) in nodes {
+            let x = entity.id().clone();
+            match parent {
+                Some(parent) => {
+                    self.entity_insert(entity);
+                    self.entity_move(&x, Some(&ids[parent])).ok();
+                }
+                None => match place {
+                    Place::Planted { name, nth } if self.root_named(name).is_none() => {
+                        let nth = nth.map_or(self.flow_arena.forest.len(), |nth| nth.min(self.flow_arena.forest.len()));
+                        self.flow_arena.plant_at(name, Node::from_id(x.clone(), entity), nth).ok();
+                        self.touch(&x);
+                    }
+                    Place::Under { parent, nth } if *parent == self.flow_arena.root => {
+                        self.entity_insert(entity);
+                        self.entity_move_at(&x, None, *nth).ok();
+                    }
+                    Place::Under { parent, nth } if self.flow_arena.materialize(parent).unwrap_or(false) => {
+                        self.entity_insert(entity);
+                        self.entity_move_at(&x, Some(parent), *nth).ok();
+                    }
+                    _ => { self.entity_insert(entity); }
+                },
+            }
+        }
+    }
+    pub fn entity_trash(&mut self, id: &EntityId) -> bool {
+        self.entity_trash_at(id, SystemTime::now())
+    }
+    /// moves id and its subtree to the trash, deleted at time; false if id doesn't exist.
+    /// entries past the retention at time are purged.
+    pub fn entity_trash_at(&mut self, id: &EntityId, time: SystemTime) -> bool {
+        let (nodes, place) = match self.subtree_take(id) { Some(taken) => taken, None => return false };
+        self.trash.entries.push(Trashed { nodes, place, deleted: time });
+        self.changes.trashed.insert(id.clone());
+        self.trash_purge(time);
+        true
+    }
+    /// moves the children of id up into its place, then trashes id alone; false if id doesn't exist.
+    pub fn entity_trash_alone(&mut self, id: &EntityId) -> bool {
+        if *id == self.flow_arena.root || !self.flow_arena.materialize(id).unwrap_or(false) { return false }
+        let parent = self.flow_arena.node_map[id].parent.clone().unwrap_or_else(|| self.flow_arena.root.clone());
+        let nth = self.entity_children(&parent).iter().position(|x| x == id).unwrap_or(usize::MAX);
+        for (i, child) in self.entity_children(id).iter().enumerate() {
+            self.entity_move_at(child, Some(&parent), nth.saturating_add(i)).ok();
+        }
+        self.entity_trash(id)
+    }
+    /// the trashed entities, oldest first.
+    pub fn trash_list(&self) -> &[Trashed] {
+        &self.trash.entries
+    }
+    /// a trashed entity by its id, or a unique hex prefix of it; see resolve_prefix.
+    pub fn trash_resolve(&self, text: &str) -> Result<EntityId, ResolveError> {
+        resolve_among(self.trash.entries.iter().map(|entry| entry.id()).collect(), text)
+    }
+    /// puts the entry of id back with its subtree: at its former place if the parent still exists,
+    /// else at the end of root.
+    pub fn trash_restore(&mut self, id: &EntityId) -> Result<(), TrashError> {
+        let idx = self.trash.entries.iter().position(|entry| entry.id() == id)
+            .ok_or_else(|| TrashError::Missing(id.clone()))?;
+        let entry = self.trash.entries.remove(idx);
+        if let Some(x) = self.subtree_clash(&entry.nodes) {
+            self.trash.entries.insert(idx, entry);
+            return Err(TrashError::Occupied(x))
+        }
+        self.changes.trashed.insert(id.clone());
+        self.subtree_put(entry.nodes, &entry.place);
+        Ok(())
+    }
+    /// removes the entries deleted longer than the retention before now; returns how many.
+    pub fn trash_purge(&mut self, now: SystemTime) -> usize {
+        let retention = match self.trash.retention { Some(retention) => retention, None => return 0 };
+        // Note: entries from the future, by a changed clock, are kept.
+        let (kept, purged): (Vec<Trashed>, Vec<Trashed>) = std::mem::take(&mut self.trash.entries).into_iter()
+            .partition(|entry| now.duration_since(entry.deleted).map_or(true, |age| age < retention));
+        self.trash.entries = kept;
+        self.changes.trashed.extend(purged.iter().map(|entry| entry.id().clone()));
+        purged.len()
+    }
+    /// None keeps trashed entities until they are restored.
+    pub fn trash_retention(&self) -> Option<Duration> {
+        self.trash.retention
+    }
+    pub fn set_trash_retention(&mut self, retention: Option<Duration>) {
+        self.trash.retention = retention;
+        self.changes.settings = true;
+    }
+}
+
+impl Compact for Place {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Place::Under { parent, nth } => {
+                0u8.encode(buf);
+                parent.encode(buf);
+                (*nth as u64).encode(buf);
+            }
+            Place::Planted { name, nth: None } => {
+                1u8.encode(buf);
+                name.encode(buf);
+            }
+            Place::Planted { name, nth: Some(nth) } => {
+                2u8.encode(buf);
+                name.encode(buf);
+                (*nth as u64).encode(buf);
+            }
+        }
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        match u8::decode(src)? {
+            0 => Ok(Place::Under { parent: EntityId::decode(src)?, nth: u64::decode(src)? as usize }),
+            1 => Ok(Place::Planted { name: String::decode(src)?, nth: None }),
+            2 => Ok(Place::Planted { name: String::decode(src)?, nth: Some(u64::decode(src)? as usize) }),
+            t => Err(CompactError::InvalidTag(t)),
+        }
+    }
+}
+
+impl Compact for Trashed {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.nodes.len() as u64).encode(buf);
+        for (entity, parent) in self.nodes.iter() {
+            entity.encode(buf);
+            parent.map(|parent| parent as u64).encode(buf);
+        }
+        self.place.encode(buf);
+        self.deleted.encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        let len = u64::decode(src)?;
+        let mut nodes = Vec::new();
+        for _ in 0..len {
+            let entity = Entity::decode(src)?;
+            nodes.push((entity, Option::<u64>::decode(src)?.map(|parent| parent as usize)));
+        }
+        Ok(Trashed { nodes, place: Place::decode(src)?, deleted: SystemTime::decode(src)? })
+    }
+}
+
+impl Compact for Trash {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.entries.encode(buf);
+        self.retention.map(|retention| retention.as_secs()).encode(buf);
+    }
+    fn decode(src: &mut &[u8]) -> Result<Self, CompactError> {
+        Ok(Trash {
+            entries: Vec::decode(src)?,
+            retention: Option::<u64>::decode(src)?.map(Duration::from_secs),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, DAY};
+
+    #[test]
+    fn trash() {
+        let mut vessel = Vessel::new();
+        let list = vessel.entity_grow();
+        let ids: Vec<EntityId> = (0..3).map(|i| {
+            let id = vessel.entity_grow();
+            vessel.entity_get_mut(&id).unwrap().face = format!("item {}", i);
+            vessel.entity_move(&id, Some(&list)).unwrap();
+            id
+        }).collect();
+        let leaf = vessel.entity_grow();
+        vessel.entity_move(&leaf, Some(&ids[1])).unwrap();
+
+        assert!(vessel.entity_trash_at(&ids[1], at(0)));
+        assert!(!vessel.entity_trash_at(&ids[1], at(0)));
+        assert_eq!(vessel.entity_children(&list), vec![ids[0].clone(), ids[2].clone()]);
+        assert!(vessel.entity_get(&leaf).is_none());
+        assert!(vessel.search("item 1").is_empty());
+        let entry = &vessel.trash_list()[0];
+        assert_eq!((entry.face(), entry.len(), entry.parent()), ("item 1", 2, Some(&list)));
+        assert_eq!(vessel.trash_resolve(&format!("{:x}", ids[1].unique())), Ok(ids[1].clone()));
+        assert!(vessel.trash_resolve(&format!("{:x}", leaf.unique())).is_err());
+
+        // back in its place, with its subtree
+        assert_eq!(vessel.trash_restore(&leaf), Err(TrashError::Missing(leaf.clone())));
+        vessel.trash_restore(&ids[1]).unwrap();
+        assert_eq!(vessel.entity_children(&list), ids);
+        assert_eq!(vessel.entity_children(&ids[1]), vec![leaf.clone()]);
+        assert!(vessel.trash_list().is_empty());
+
+        // the parent is gone, so to the end of root
+        vessel.entity_trash_at(&ids[2], at(0));
+        vessel.entity_decay(&list);
+        vessel.trash_restore(&ids[2]).unwrap();
+        assert_eq!(vessel.entity_parent(&ids[2]), None);
+        assert_eq!(vessel.entity_top().last(), Some(&ids[2]));
+
+        // purged after the retention, when the next entity is trashed
+        vessel.entity_trash_at(&ids[0], at(0));
+        vessel.entity_trash_at(&ids[2], at(10 * DAY));
+        assert_eq!(vessel.trash_list().len(), 2);
+        vessel.entity_trash_at(&leaf, at(31 * DAY));
+        assert_eq!(vessel.trash_list().iter().map(|entry| entry.id()).collect::<Vec<_>>(), vec![&ids[2], &leaf]);
+        vessel.set_trash_retention(None);
+        assert_eq!(vessel.trash_purge(at(u32::MAX as u64)), 0);
+
+        // alone, the children move up into its place
+        vessel.set_trash_retention(Some(TRASH_RETENTION));
+        let (top, child) = (vessel.entity_grow(), vessel.entity_grow());
+        vessel.entity_move(&child, Some(&top)).unwrap();
+        let before = vessel.entity_top();
+        assert!(vessel.entity_trash_alone(&top));
+        let at_top = before.iter().map(|x| if x == &top { &child } else { x }).cloned().collect::<Vec<_>>();
+        assert_eq!(vessel.entity_top(), at_top);
+        assert_eq!(vessel.trash_list().last().map(|entry| (entry.id(), entry.len(), entry.parent())), Some((&top, 1, None)));
+        vessel.set_trash_retention(None);
+
+        // saved with the vessel
+        let json = Vessel::from_json(&vessel.to_json().unwrap()).unwrap();
+        assert_eq!(json.trash, vessel.trash);
+        let compact = Vessel::from_compact(&vessel.to_compact()).unwrap();
+        assert_eq!(compact.trash, vessel.trash);
+    }
+}